@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+
+use crate::clock::Clock;
+use crate::message::Message;
+
+/// Alerts buffered to be sent together as one combined Telegram message.
+pub(crate) struct Batch {
+    clock: Arc<dyn Clock>,
+    /// how long after the first buffered alert the batch is flushed
+    window: Duration,
+    /// flush early once this many alerts are buffered
+    max_size: usize,
+    /// alerts at or above this profit skip batching and are sent right away
+    pub(crate) bypass_profit: Option<Decimal>,
+    pending: Mutex<(Vec<Message>, Option<Instant>)>,
+}
+
+impl Batch {
+    pub(crate) fn new(
+        clock: Arc<dyn Clock>,
+        window: Duration,
+        max_size: usize,
+        bypass_profit: Option<Decimal>,
+    ) -> Self {
+        Batch {
+            clock,
+            window,
+            max_size,
+            bypass_profit,
+            pending: Mutex::new((Vec::new(), None)),
+        }
+    }
+
+    /// Buffer `msg`, returning the whole batch if that filled it.
+    pub(crate) fn push(&self, msg: Message) -> Option<Vec<Message>> {
+        let now = self.clock.now();
+        let mut pending = self.pending.lock().unwrap();
+        let (messages, first_at) = &mut *pending;
+
+        messages.push(msg);
+        first_at.get_or_insert(now);
+
+        if messages.len() >= self.max_size {
+            *first_at = None;
+            return Some(std::mem::take(messages));
+        }
+
+        None
+    }
+
+    /// The buffered alerts, if the window since the first one has elapsed.
+    pub(crate) fn take_due(&self) -> Option<Vec<Message>> {
+        let now = self.clock.now();
+        let mut pending = self.pending.lock().unwrap();
+        let (messages, first_at) = &mut *pending;
+
+        match first_at {
+            Some(first) if now.duration_since(*first) >= self.window => {
+                *first_at = None;
+                Some(std::mem::take(messages))
+            }
+            _ => None,
+        }
+    }
+
+    /// Everything still buffered, due or not, for flushing on exit.
+    pub(crate) fn take_all(&self) -> Vec<Message> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.1 = None;
+        std::mem::take(&mut pending.0)
+    }
+}
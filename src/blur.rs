@@ -0,0 +1,79 @@
+use rust_decimal::Decimal;
+use tracing::warn;
+
+use crate::error::{get_json, ApiProvider, MessageError};
+
+/// How a direct Blur bid is used against a Blur-sourced Reservoir top bid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BlurBidMode {
+    /// keep Reservoir's price and only warn when the two disagree
+    CrossCheck,
+    /// use Blur's own price in place of Reservoir's
+    Replace,
+}
+
+/// Credentials and settings for querying Blur's bid book directly. Only built when
+/// `BLUR_AUTH_TOKEN` and `BLUR_WALLET_ADDRESS` are both set.
+#[derive(Clone, Debug)]
+pub(crate) struct BlurConfig {
+    auth_token: String,
+    wallet_address: String,
+    pub(crate) mode: BlurBidMode,
+    /// percent difference from the Reservoir price above which a warning is shown
+    pub(crate) discrepancy_pct: f64,
+}
+
+impl BlurConfig {
+    pub(crate) fn from_env() -> Option<Self> {
+        let auth_token = dotenv::var("BLUR_AUTH_TOKEN").ok()?;
+        let wallet_address = dotenv::var("BLUR_WALLET_ADDRESS").ok()?;
+
+        let mode = match dotenv::var("BLUR_BID_MODE").as_deref() {
+            Ok("replace") => BlurBidMode::Replace,
+            Ok("crosscheck") | Err(_) => BlurBidMode::CrossCheck,
+            Ok(other) => {
+                warn!("Unknown BLUR_BID_MODE {}, using crosscheck", other);
+                BlurBidMode::CrossCheck
+            }
+        };
+
+        Some(BlurConfig {
+            auth_token,
+            wallet_address,
+            mode,
+            discrepancy_pct: crate::config::var_or("BLUR_DISCREPANCY_PCT", 5f64),
+        })
+    }
+
+    /// The best executable collection bid on Blur, in ETH. Blur bids are collection wide, so
+    /// this is the bid any token in the collection can be sold into.
+    pub(crate) async fn top_bid(&self, collection: &str) -> Result<Decimal, MessageError> {
+        let client = crate::http::client();
+
+        let url = format!(
+            "https://core-api.prod.blur.io/v1/collections/{}/executable-bids?filters=%7B%22criteria%22%3A%7B%22type%22%3A%22COLLECTION%22%2C%22value%22%3A%7B%7D%7D%7D",
+            collection
+        );
+
+        let req = client.get(url).header("accept", "application/json").header(
+            reqwest::header::COOKIE,
+            format!(
+                "authToken={}; walletAddress={}",
+                self.auth_token, self.wallet_address
+            ),
+        );
+
+        let json = get_json(ApiProvider::Blur, req).await?;
+
+        // price levels are sorted best first
+        json["priceLevels"][0]["price"]
+            .as_str()
+            .and_then(|price| price.parse::<Decimal>().ok())
+            .ok_or_else(|| {
+                MessageError::parse(
+                    ApiProvider::Blur,
+                    format!("missing price level: {:?}", json["priceLevels"]),
+                )
+            })
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::clock::{Clock, SystemClock};
+use crate::config;
+use crate::error::ApiProvider;
+
+static BREAKERS: OnceLock<Breakers> = OnceLock::new();
+
+/// The process wide breakers, consulted by `get_json` for every HTTP call. They live here
+/// rather than on `AppState` because the fetchers calling `get_json` don't take the state.
+pub(crate) fn breakers() -> &'static Breakers {
+    BREAKERS.get_or_init(|| {
+        Breakers::new(
+            Arc::new(SystemClock),
+            config::var_or("BREAKER_FAILURES", 0),
+            Duration::from_secs(config::var_or("BREAKER_COOLDOWN_SECS", 60)),
+        )
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BreakerState {
+    /// calls go through
+    Closed,
+    /// calls are skipped until the cooldown ends
+    Open,
+    /// the cooldown ended and a single probe call decides whether to close again
+    HalfOpen,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    /// set while the circuit is open; calls before this instant are skipped
+    open_until: Option<Instant>,
+    /// a probe call has been let through and hasn't reported back yet
+    probing: bool,
+}
+
+/// Per-provider circuit breakers. After `threshold` consecutive failures a provider is skipped
+/// for `cooldown`, then one probe call is let through: success closes the circuit, failure
+/// opens it for another cooldown.
+pub(crate) struct Breakers {
+    clock: Arc<dyn Clock>,
+    /// 0 disables the breakers
+    threshold: u32,
+    cooldown: Duration,
+    circuits: Mutex<HashMap<ApiProvider, Circuit>>,
+}
+
+impl Breakers {
+    pub(crate) fn new(clock: Arc<dyn Clock>, threshold: u32, cooldown: Duration) -> Self {
+        Breakers {
+            clock,
+            threshold,
+            cooldown,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a call to `provider` should be made now.
+    pub(crate) fn allow(&self, provider: ApiProvider) -> bool {
+        if self.threshold == 0 {
+            return true;
+        }
+
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(provider).or_default();
+
+        match circuit.open_until {
+            None => true,
+            Some(open_until) if now < open_until => false,
+            Some(_) => {
+                // let this call probe, and hold everything else off for another cooldown in
+                // case it never reports back
+                circuit.open_until = Some(now + self.cooldown);
+                circuit.probing = true;
+                true
+            }
+        }
+    }
+
+    /// Record the outcome of a call `allow` let through.
+    pub(crate) fn record(&self, provider: ApiProvider, success: bool) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(provider).or_default();
+
+        if success {
+            if circuit.open_until.is_some() {
+                info!("{} recovered, closing its circuit breaker", provider);
+            }
+            *circuit = Circuit::default();
+            return;
+        }
+
+        circuit.consecutive_failures += 1;
+        if circuit.probing || circuit.consecutive_failures == self.threshold {
+            warn!(
+                "{} failed {} times in a row, skipping it for {}s",
+                provider,
+                circuit.consecutive_failures,
+                self.cooldown.as_secs()
+            );
+            circuit.open_until = Some(now + self.cooldown);
+            circuit.probing = false;
+        }
+    }
+
+    /// The state of every provider called so far, for the metrics.
+    pub(crate) fn states(&self) -> BTreeMap<ApiProvider, BreakerState> {
+        let now = self.clock.now();
+
+        self.circuits
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(provider, circuit)| {
+                let state = match circuit.open_until {
+                    None => BreakerState::Closed,
+                    Some(_) if circuit.probing => BreakerState::HalfOpen,
+                    Some(open_until) if now < open_until => BreakerState::Open,
+                    Some(_) => BreakerState::HalfOpen,
+                };
+                (*provider, state)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::PathBuf;
+
+use ethers::types::{Address, H256};
+use tracing::warn;
+
+use crate::config;
+use crate::fatal::Fatal;
+
+/// Flooring's vault on Ethereum, where `FragmentNft` and `ClaimRandomNft` are emitted.
+const ETHEREUM_FLOORING: &str = "0x3eb879cc9a0Ef4C6f1d870A40ae187768c278Da2";
+/// The getter contract answering `collectionInfo` and `getSafeBox` on Ethereum.
+const ETHEREUM_FLOORING_INFO: &str = "0x8ad7892f15e6a3a1c0eecf83c30f414227434540";
+
+/// Everything that differs between the chains Flooring is deployed on.
+#[derive(Clone, Debug)]
+pub(crate) struct ChainConfig {
+    /// shown on alerts when more than one chain is monitored, and used as the dexscreener slug
+    pub(crate) name: String,
+    /// passed to ParaSwap as `network`
+    pub(crate) chain_id: u64,
+    pub(crate) wss_rpc: String,
+    pub(crate) http_rpc: String,
+    /// block to start processing from, `None` for the latest block
+    pub(crate) starting_block: Option<u64>,
+    /// the vaults emitting `FragmentNft` and `ClaimRandomNft`, without duplicates
+    pub(crate) flooring: Vec<Address>,
+    /// the getter contract answering `collectionInfo` and `getSafeBox`
+    pub(crate) flooring_info: Address,
+    /// block explorer base url, transactions are linked as `{explorer_url}/tx/{hash}`
+    pub(crate) explorer_url: String,
+    /// only events for these collections are processed, every collection when empty
+    pub(crate) collections: Vec<Address>,
+    /// where the last handled event is saved, to resume from when no starting block is set
+    pub(crate) checkpoint_file: Option<PathBuf>,
+}
+
+impl ChainConfig {
+    /// Every chain to monitor. With `CHAINS` unset this is just Ethereum, configured by
+    /// `WSS_RPC`, `HTTP_RPC`, `STARTING_BLOCK`, `FLOORING_ADDRESSES`, `COLLECTION_ALLOWLIST` and
+    /// `CHECKPOINT_FILE`. Otherwise `CHAINS` is a `,` separated list of names, and each is
+    /// configured by the same variables prefixed with `<NAME>_`, plus `<NAME>_CHAIN_ID`,
+    /// `<NAME>_FLOORING_INFO` and `<NAME>_EXPLORER_URL`, which default to the Ethereum values.
+    pub(crate) fn from_env() -> Result<Vec<Self>, Fatal> {
+        let Ok(chains) = dotenv::var("CHAINS") else {
+            return Ok(vec![ChainConfig::ethereum().with_rpc("")?]);
+        };
+
+        chains
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let prefix = format!("{}_", name.to_uppercase());
+                let var = |key: &str| format!("{}{}", prefix, key);
+                let ethereum = ChainConfig::ethereum().with_rpc(&prefix)?;
+
+                Ok(ChainConfig {
+                    name: name.to_lowercase(),
+                    chain_id: config::var_or(&var("CHAIN_ID"), ethereum.chain_id),
+                    flooring_info: config::var_or(&var("FLOORING_INFO"), ethereum.flooring_info),
+                    explorer_url: config::var_or(&var("EXPLORER_URL"), ethereum.explorer_url),
+                    ..ethereum
+                })
+            })
+            .collect()
+    }
+
+    /// Read the RPC urls, starting block, vault addresses, collection allowlist and checkpoint
+    /// file from the variables named with `prefix`.
+    fn with_rpc(self, prefix: &str) -> Result<Self, Fatal> {
+        Ok(ChainConfig {
+            wss_rpc: dotenv::var(format!("{}WSS_RPC", prefix)).unwrap_or_default(),
+            // left empty when unset, which is refused at startup
+            http_rpc: dotenv::var(format!("{}HTTP_RPC", prefix)).unwrap_or_default(),
+            starting_block: match config::var_or(&format!("{}STARTING_BLOCK", prefix), 0) {
+                0 => None,
+                block => Some(block),
+            },
+            flooring: addresses(&format!("{}FLOORING_ADDRESSES", prefix))?.unwrap_or(self.flooring),
+            collections: addresses(&format!("{}COLLECTION_ALLOWLIST", prefix))?.unwrap_or_default(),
+            checkpoint_file: dotenv::var(format!("{}CHECKPOINT_FILE", prefix))
+                .ok()
+                .map(Into::into),
+            ..self
+        })
+    }
+
+    fn ethereum() -> Self {
+        ChainConfig {
+            name: "ethereum".to_string(),
+            chain_id: 1,
+            wss_rpc: String::new(),
+            http_rpc: String::new(),
+            starting_block: None,
+            flooring: vec![ETHEREUM_FLOORING.parse().expect("valid flooring address")],
+            flooring_info: ETHEREUM_FLOORING_INFO
+                .parse()
+                .expect("valid flooring info address"),
+            explorer_url: "https://etherscan.io".to_string(),
+            collections: Vec::new(),
+            checkpoint_file: None,
+        }
+    }
+
+    /// Link to `tx_hash` on this chain's block explorer.
+    pub(crate) fn tx_link(&self, tx_hash: &str) -> String {
+        format!("{}/tx/{}", self.explorer_url.trim_end_matches('/'), tx_hash)
+    }
+
+    /// Whether events for `collection` should be processed.
+    pub(crate) fn allows(&self, collection: &Address) -> bool {
+        self.collections.is_empty() || self.collections.contains(collection)
+    }
+
+    /// The allowlist as values for `FragmentNft`'s indexed `collection` topic, so the node only
+    /// sends matching events. `None` when every collection is wanted, or when there are more
+    /// than `TOPIC_FILTER_MAX` (default 100) for providers to accept, leaving it all to
+    /// `allows`.
+    pub(crate) fn collection_topics(&self) -> Option<Vec<H256>> {
+        if self.collections.is_empty() {
+            return None;
+        }
+
+        let max = config::var_or("TOPIC_FILTER_MAX", 100);
+        if self.collections.len() > max {
+            warn!(
+                "{} allowlisted collections on {} is more than TOPIC_FILTER_MAX ({}), filtering them client side",
+                self.collections.len(),
+                self.name,
+                max
+            );
+            return None;
+        }
+
+        Some(
+            self.collections
+                .iter()
+                .map(|&collection| collection.into())
+                .collect(),
+        )
+    }
+}
+
+/// Parse a `,` separated address list, dropping duplicates with a warning so the same vault is
+/// never subscribed to twice. `None` when `key` is unset, and an error when it is set without a
+/// single valid address.
+fn addresses(key: &str) -> Result<Option<Vec<Address>>, Fatal> {
+    let Ok(value) = dotenv::var(key) else {
+        return Ok(None);
+    };
+
+    let mut addresses = Vec::new();
+    for entry in value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        match entry.parse::<Address>() {
+            Ok(address) if addresses.contains(&address) => {
+                warn!("{key} lists {entry} more than once, using it once");
+            }
+            Ok(address) => addresses.push(address),
+            Err(_) => warn!("Ignoring malformed {key} entry: {entry}"),
+        }
+    }
+
+    if addresses.is_empty() {
+        return Err(Fatal::Config(format!("{} has no valid addresses", key)));
+    }
+
+    Ok(Some(addresses))
+}
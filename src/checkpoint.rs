@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use ethers::prelude::LogMeta;
+use tracing::{error, warn};
+
+/// The last event handled on a chain, persisted so a restart resumes right after it.
+///
+/// Resuming starts from the checkpointed block, which may hold events that were already handled,
+/// so events at or before the checkpointed `(block, log_index)` are skipped rather than relying
+/// on dedup to catch them. With several events in flight, the checkpoint only moves past an event
+/// once every event before it has been handled too, so a restart never skips one.
+pub(crate) struct Checkpoint {
+    /// where the position is persisted, if anywhere
+    path: Option<PathBuf>,
+    progress: Mutex<Progress>,
+    /// the position the previous run stopped at, when this run resumes from it
+    resumed_from: Option<(u64, u64)>,
+}
+
+#[derive(Default)]
+struct Progress {
+    /// `(block, log_index)` of the last event handled with none before it still in flight
+    last: Option<(u64, u64)>,
+    /// events started but not handled yet, with how many copies of each are in flight
+    in_flight: BTreeMap<(u64, u64), usize>,
+    /// events handled while one before them is still in flight
+    handled: BTreeSet<(u64, u64)>,
+}
+
+impl Checkpoint {
+    /// Read the previous run's position from `path`. It is only resumed from when `resume` is
+    /// set, i.e. when no starting block is configured.
+    pub(crate) fn load(path: Option<PathBuf>, resume: bool) -> Self {
+        let last = path.as_ref().and_then(|path| {
+            let contents = std::fs::read_to_string(path).ok()?;
+            let json = serde_json::from_str::<serde_json::Value>(&contents)
+                .map_err(|e| error!("Error reading checkpoint file: {:?}", e))
+                .ok()?;
+            Some((json["block"].as_u64()?, json["log_index"].as_u64()?))
+        });
+
+        Checkpoint {
+            path,
+            progress: Mutex::new(Progress {
+                last,
+                ..Progress::default()
+            }),
+            resumed_from: last.filter(|_| resume),
+        }
+    }
+
+    /// The block to resume from, if resuming.
+    pub(crate) fn resume_from(&self) -> Option<(u64, u64)> {
+        self.resumed_from
+    }
+
+    /// Whether the previous run already handled this event.
+    pub(crate) fn handled(&self, meta: &LogMeta) -> bool {
+        self.resumed_from
+            .is_some_and(|resumed_from| position(meta) <= resumed_from)
+    }
+
+    /// Note that processing of an event started, holding the checkpoint before it until it is
+    /// finished. Called in the order events arrive.
+    pub(crate) fn start(&self, meta: &LogMeta) {
+        if self.path.is_none() {
+            return;
+        }
+
+        let mut progress = self.progress.lock().unwrap();
+        *progress.in_flight.entry(position(meta)).or_default() += 1;
+    }
+
+    /// Note that processing of an event finished, persisting the checkpoint if it can move. An
+    /// event that was not `handled` keeps holding it back for the rest of the run, so the next
+    /// run retries it.
+    pub(crate) fn finish(&self, meta: &LogMeta, handled: bool) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let position = position(meta);
+        if !handled {
+            warn!(
+                "Event at block {} failed, the checkpoint stays before it until restart",
+                position.0
+            );
+            return;
+        }
+
+        // held while writing, so an older checkpoint never overwrites a newer one
+        let mut progress = self.progress.lock().unwrap();
+        let Some(last) = progress.finish(position) else {
+            return;
+        };
+
+        let json = serde_json::json!({
+            "block": last.0,
+            "log_index": last.1,
+        });
+
+        if let Err(e) = std::fs::write(path, json.to_string()) {
+            error!("Error writing checkpoint file: {:?}", e);
+        }
+    }
+}
+
+impl Progress {
+    /// Mark `position` handled, returning the new checkpoint if it moved.
+    fn finish(&mut self, position: (u64, u64)) -> Option<(u64, u64)> {
+        if let Some(copies) = self.in_flight.get_mut(&position) {
+            *copies -= 1;
+            if *copies == 0 {
+                self.in_flight.remove(&position);
+            }
+        }
+        self.handled.insert(position);
+
+        // everything before the earliest event still in flight is done
+        let ready = match self.in_flight.keys().next() {
+            Some(earliest) => {
+                let rest = self.handled.split_off(earliest);
+                std::mem::replace(&mut self.handled, rest)
+            }
+            None => std::mem::take(&mut self.handled),
+        };
+
+        // events from different sources may interleave, never move the checkpoint back
+        let next = ready.last().copied()?;
+        if self.last.is_some_and(|last| next <= last) {
+            return None;
+        }
+        self.last = Some(next);
+
+        self.last
+    }
+}
+
+fn position(meta: &LogMeta) -> (u64, u64) {
+    (meta.block_number.as_u64(), meta.log_index.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(positions: &[(u64, u64)]) -> Progress {
+        let mut progress = Progress::default();
+        for position in positions {
+            *progress.in_flight.entry(*position).or_default() += 1;
+        }
+        progress
+    }
+
+    #[test]
+    fn waits_for_earlier_events_still_in_flight() {
+        let mut progress = started(&[(10, 0), (10, 1), (11, 0)]);
+
+        assert_eq!(progress.finish((11, 0)), None);
+        assert_eq!(progress.finish((10, 0)), Some((10, 0)));
+        // the last one in flight lets the checkpoint catch up past the others
+        assert_eq!(progress.finish((10, 1)), Some((11, 0)));
+    }
+
+    #[test]
+    fn failed_event_holds_the_checkpoint() {
+        let mut progress = started(&[(10, 0), (11, 0), (12, 0)]);
+
+        // (10, 0) failed, so it is never finished
+        assert_eq!(progress.finish((11, 0)), None);
+        assert_eq!(progress.finish((12, 0)), None);
+        assert_eq!(progress.last, None);
+    }
+
+    #[test]
+    fn duplicate_deliveries_all_have_to_finish() {
+        let mut progress = started(&[(10, 0), (10, 0), (11, 0)]);
+
+        assert_eq!(progress.finish((10, 0)), None);
+        assert_eq!(progress.finish((11, 0)), None);
+        assert_eq!(progress.finish((10, 0)), Some((11, 0)));
+    }
+
+    #[test]
+    fn never_moves_back() {
+        let mut progress = Progress {
+            last: Some((20, 0)),
+            ..started(&[(15, 0)])
+        };
+
+        assert_eq!(progress.finish((15, 0)), None);
+        assert_eq!(progress.last, Some((20, 0)));
+    }
+}
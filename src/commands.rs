@@ -0,0 +1,306 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use ethers::types::{Address, U256};
+use rust_decimal::Decimal;
+use teloxide::{prelude::*, types::ParseMode, utils::command::BotCommands};
+use tracing::{debug, warn};
+
+use crate::error::{ApiProvider, MessageError};
+use crate::state::AppState;
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "Flooring monitor commands:")]
+pub(crate) enum Command {
+    #[command(description = "show monitor status and paper trading profit")]
+    Status,
+    #[command(description = "reset the paper trading ledger")]
+    ResetLedger,
+    #[command(description = "stop alerting on a collection address")]
+    Mute(String),
+    #[command(description = "resume alerting on a collection address")]
+    Unmute(String),
+    #[command(description = "list muted collections")]
+    Muted,
+    #[command(
+        description = "price a token now: /quote <collection> <token_id>",
+        parse_with = "split"
+    )]
+    Quote {
+        collection: String,
+        token_id: String,
+    },
+    #[command(description = "rank the monitored collections by current arbitrage spread")]
+    Scan,
+}
+
+/// Telegram user ids allowed to run commands, from `ADMIN_USER_IDS`.
+#[derive(Clone, Debug)]
+struct Admins(Arc<HashSet<u64>>);
+
+impl Admins {
+    fn from_env() -> Self {
+        let admins = dotenv::var("ADMIN_USER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|id| !id.trim().is_empty())
+            .filter_map(|id| match id.trim().parse::<u64>() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    warn!("Ignoring malformed admin user id: {}", id);
+                    None
+                }
+            })
+            .collect::<HashSet<_>>();
+
+        if admins.is_empty() {
+            warn!("ADMIN_USER_IDS is empty, every command will be refused");
+        }
+
+        Admins(Arc::new(admins))
+    }
+
+    fn allows(&self, msg: &Message) -> bool {
+        msg.from().is_some_and(|user| self.0.contains(&user.id.0))
+    }
+}
+
+/// Answer bot commands until the process exits.
+pub(crate) async fn run(bot: Bot, state: Arc<AppState>) {
+    let handler = Update::filter_message()
+        .filter_command::<Command>()
+        .endpoint(authorize);
+
+    Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![state, Admins::from_env()])
+        .build()
+        .dispatch()
+        .await;
+}
+
+/// Only admins get their commands answered, everyone else is refused.
+async fn authorize(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    state: Arc<AppState>,
+    admins: Admins,
+) -> ResponseResult<()> {
+    if !admins.allows(&msg) {
+        bot.send_message(
+            msg.chat.id,
+            "You are not allowed to use this bot's commands",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    answer(bot, msg, cmd, state).await
+}
+
+async fn answer(bot: Bot, msg: Message, cmd: Command, state: Arc<AppState>) -> ResponseResult<()> {
+    let reply = match cmd {
+        Command::Status => {
+            let ledger = state.ledger.snapshot();
+            format!(
+                "Events processed: {}\nAlerts sent: {}\nPaper trading profit: {} ETH over {} alerts ({} hours)",
+                state.metrics.events_processed.load(std::sync::atomic::Ordering::Relaxed),
+                state.metrics.alerts_sent.load(std::sync::atomic::Ordering::Relaxed),
+                ledger.total_profit,
+                ledger.alerts,
+                ledger.since.elapsed().as_secs() / 3600,
+            )
+        }
+        Command::ResetLedger => {
+            state.ledger.reset();
+            "Paper trading ledger reset".to_string()
+        }
+        Command::Mute(collection) => match parse_collection(&collection) {
+            Some(collection) if state.mute_list.mute(&collection) => {
+                format!("Muted {}", collection)
+            }
+            Some(collection) => format!("{} is already muted", collection),
+            None => format!("Not a collection address: {}", collection),
+        },
+        Command::Unmute(collection) => match parse_collection(&collection) {
+            Some(collection) if state.mute_list.unmute(&collection) => {
+                format!("Unmuted {}", collection)
+            }
+            Some(collection) => format!("{} is not muted", collection),
+            None => format!("Not a collection address: {}", collection),
+        },
+        Command::Muted => {
+            let muted = state.mute_list.list();
+            if muted.is_empty() {
+                "No collections are muted".to_string()
+            } else {
+                format!("Muted collections:\n{}", muted.join("\n"))
+            }
+        }
+        Command::Quote {
+            collection,
+            token_id,
+        } => return quote(bot, msg, state, collection, token_id).await,
+        Command::Scan => return scan(bot, msg, state).await,
+    };
+
+    bot.send_message(msg.chat.id, reply).await?;
+
+    Ok(())
+}
+
+/// Price one token on demand and reply with the same html an alert would have.
+async fn quote(
+    bot: Bot,
+    msg: Message,
+    state: Arc<AppState>,
+    collection: String,
+    token_id: String,
+) -> ResponseResult<()> {
+    let reply = match (
+        collection.trim().parse::<Address>(),
+        U256::from_dec_str(token_id.trim()),
+    ) {
+        (Ok(collection), Ok(token_id)) => {
+            crate::message::Message::quote(&state, collection, token_id)
+                .await
+                .to_string()
+        }
+        (Err(_), _) => format!("Not a collection address: {}", collection),
+        (_, Err(_)) => format!("Not a token id: {}", token_id),
+    };
+
+    bot.send_message(msg.chat.id, reply)
+        .parse_mode(ParseMode::Html)
+        .await?;
+
+    Ok(())
+}
+
+/// One collection's spreads over the sampled tokens, top bid minus the MuToken cost of one NFT.
+struct Spread {
+    collection: String,
+    average: Decimal,
+    best: Decimal,
+    /// sampled tokens that had a bid
+    bids: usize,
+    sampled: usize,
+}
+
+/// Rank `MONITORED_COLLECTIONS` by arbitrage spread. Redeeming gives a random NFT from the vault,
+/// so collections are ranked by the average spread over a sample of the vault's tokens, at most
+/// `SCAN_SAMPLE_SIZE` (default 5, capped at 20) per collection to go easy on rate limits.
+async fn scan(bot: Bot, msg: Message, state: Arc<AppState>) -> ResponseResult<()> {
+    let collections = crate::config::collections("MONITORED_COLLECTIONS");
+    if collections.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "MONITORED_COLLECTIONS is empty, nothing to scan",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let sample_size = crate::config::var_or("SCAN_SAMPLE_SIZE", 5usize).clamp(1, 20);
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Scanning {} collections, {} tokens each...",
+            collections.len(),
+            sample_size
+        ),
+    )
+    .await?;
+
+    let mut ranked = Vec::new();
+    let mut failed = Vec::new();
+    for collection in &collections {
+        match scan_collection(&state, collection, sample_size).await {
+            Ok(spread) => ranked.push(spread),
+            Err(e) => {
+                warn!("Error scanning {}: {}", collection, e);
+                failed.push(collection.as_str());
+            }
+        }
+    }
+    ranked.sort_by_key(|spread| std::cmp::Reverse(spread.average));
+
+    let mut reply = "Average top bid minus MuToken cost per NFT:".to_string();
+    for (rank, spread) in ranked.iter().enumerate() {
+        reply.push_str(&format!(
+            "\n{}. {}: {} ETH (best {} ETH, {} of {} sampled tokens with bids)",
+            rank + 1,
+            spread.collection,
+            spread.average.round_dp(4),
+            spread.best.round_dp(4),
+            spread.bids,
+            spread.sampled
+        ));
+    }
+    if !failed.is_empty() {
+        reply.push_str(&format!("\nCould not scan: {}", failed.join(", ")));
+    }
+
+    bot.send_message(msg.chat.id, reply).await?;
+
+    Ok(())
+}
+
+async fn scan_collection(
+    state: &AppState,
+    collection: &str,
+    sample_size: usize,
+) -> Result<Spread, MessageError> {
+    let message = crate::message::Message::default();
+    let cost = message
+        .get_mu_token_details(state, collection)
+        .await?
+        .cost();
+
+    let mut token_ids = Vec::new();
+    for vault in &state.chain.flooring {
+        if token_ids.len() >= sample_size {
+            break;
+        }
+        token_ids.extend(
+            crate::message::Message::get_owned_tokens(
+                *vault,
+                collection,
+                sample_size - token_ids.len(),
+            )
+            .await?,
+        );
+    }
+
+    let mut spreads = Vec::new();
+    for token_id in &token_ids {
+        match message.get_top_bid(collection, *token_id).await {
+            Ok(top_bid) => spreads.push(top_bid.price() - cost),
+            Err(e) => debug!("No top bid for {} {}: {}", collection, token_id, e),
+        }
+    }
+
+    let Some(best) = spreads.iter().max().copied() else {
+        return Err(MessageError::no_data(
+            ApiProvider::Reservoir,
+            format!("no bids on {} sampled vault tokens", token_ids.len()),
+        ));
+    };
+
+    Ok(Spread {
+        collection: collection.to_string(),
+        average: spreads.iter().sum::<Decimal>() / Decimal::from(spreads.len()),
+        best,
+        bids: spreads.len(),
+        sampled: token_ids.len(),
+    })
+}
+
+/// Normalize a collection address to the lowercase hex used everywhere else.
+fn parse_collection(collection: &str) -> Option<String> {
+    collection
+        .trim()
+        .parse::<Address>()
+        .ok()
+        .map(|address| format!("{:#x}", address))
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use tracing::warn;
+
+/// Read an optional env var, falling back to `default` when it is unset or fails to parse.
+pub(crate) fn var_or<T: FromStr>(key: &str, default: T) -> T {
+    match dotenv::var(key) {
+        Ok(value) => value.parse::<T>().unwrap_or_else(|_| {
+            warn!("Could not parse {key}={value}, using default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Read an optional env var, `None` when it is unset or fails to parse.
+pub(crate) fn var_opt<T: FromStr>(key: &str) -> Option<T> {
+    let value = dotenv::var(key).ok()?;
+    value
+        .parse::<T>()
+        .map_err(|_| warn!("Could not parse {key}={value}, ignoring it"))
+        .ok()
+}
+
+/// Read a `,` separated list, with each item trimmed. Empty when unset.
+pub(crate) fn list(key: &str) -> Vec<String> {
+    dotenv::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Read a `,` separated list of collection addresses, lowercased. Empty when unset.
+pub(crate) fn collections(key: &str) -> Vec<String> {
+    dotenv::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(|collection| collection.trim().to_lowercase())
+        .filter(|collection| !collection.is_empty())
+        .collect()
+}
+
+/// Read a per-collection env var of `;` separated `address=value` entries, keyed by lowercase
+/// collection address. Entries that fail to parse are logged and skipped. Also used for other
+/// `name=value` maps, such as per bid source settings.
+pub(crate) fn per_collection<T: FromStr>(key: &str) -> HashMap<String, T> {
+    let Ok(value) = dotenv::var(key) else {
+        return HashMap::new();
+    };
+
+    value
+        .split(';')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let parsed = entry
+                .split_once('=')
+                .and_then(|(collection, value)| Some((collection, value.trim().parse().ok()?)));
+
+            match parsed {
+                Some((collection, value)) => Some((collection.trim().to_lowercase(), value)),
+                None => {
+                    warn!("Ignoring malformed {key} entry: {entry}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// Per-key alert cooldown. A key that alerted less than `window` ago is suppressed.
+pub(crate) struct Cooldown {
+    clock: Arc<dyn Clock>,
+    window: Duration,
+    last_alert: Mutex<HashMap<String, Instant>>,
+}
+
+impl Cooldown {
+    pub(crate) fn new(clock: Arc<dyn Clock>, window: Duration) -> Self {
+        Cooldown {
+            clock,
+            window,
+            last_alert: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true (and records the alert) if `key` is not cooling down.
+    pub(crate) fn try_acquire(&self, key: &str) -> bool {
+        let now = self.clock.now();
+        let mut last_alert = self.last_alert.lock().unwrap();
+
+        if let Some(last) = last_alert.get(key) {
+            if now.duration_since(*last) < self.window {
+                return false;
+            }
+        }
+
+        last_alert.insert(key.to_string(), now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn suppresses_inside_window_and_passes_after() {
+        let clock = Arc::new(MockClock::new());
+        let cooldown = Cooldown::new(clock.clone(), Duration::from_secs(60));
+
+        assert!(cooldown.try_acquire("0xabc"));
+
+        clock.advance(Duration::from_secs(59));
+        assert!(!cooldown.try_acquire("0xabc"));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(cooldown.try_acquire("0xabc"));
+    }
+
+    #[test]
+    fn keys_cool_down_independently() {
+        let clock = Arc::new(MockClock::new());
+        let cooldown = Cooldown::new(clock, Duration::from_secs(60));
+
+        assert!(cooldown.try_acquire("0xabc"));
+        assert!(cooldown.try_acquire("0xdef"));
+        assert!(!cooldown.try_acquire("0xabc"));
+    }
+
+    #[test]
+    fn suppressed_alert_does_not_extend_window() {
+        let clock = Arc::new(MockClock::new());
+        let cooldown = Cooldown::new(clock.clone(), Duration::from_secs(60));
+
+        assert!(cooldown.try_acquire("0xabc"));
+        clock.advance(Duration::from_secs(30));
+        assert!(!cooldown.try_acquire("0xabc"));
+        clock.advance(Duration::from_secs(30));
+        assert!(cooldown.try_acquire("0xabc"));
+    }
+}
@@ -0,0 +1,27 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::message::{Message, CSV_HEADER};
+
+/// Append one row per token of `msg` to the CSV at `path`, writing the header if the file is new.
+pub(crate) fn append(path: &Path, msg: &Message) -> std::io::Result<()> {
+    let is_new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    if is_new {
+        writeln!(file, "{}", CSV_HEADER)?;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    for row in msg.csv_rows(timestamp) {
+        writeln!(file, "{}", row)?;
+    }
+
+    Ok(())
+}
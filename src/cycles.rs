@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ethers::{
+    contract::Contract,
+    core::types::{ValueOrArray, U256},
+    providers::{Http, Middleware, Provider},
+};
+
+use rust_decimal::Decimal;
+use tracing::{error, info};
+
+use crate::clock::Clock;
+use crate::message::Message;
+use crate::state::AppState;
+use crate::{config, notifier, ClaimRandomNftFilter};
+
+/// A fragmented token waiting to be redeemed.
+#[derive(Clone, Debug)]
+struct Fragmented {
+    tx_hash: String,
+    /// MuToken cost of one NFT when it was fragmented
+    cost: Option<Decimal>,
+    at: Instant,
+}
+
+/// Matches redemptions against earlier fragments of the same token, to report completed
+/// fragment/redeem cycles.
+///
+/// A fragment is kept for `window` after its event. Expired fragments are dropped whenever the
+/// store is touched, and a redemption consumes its fragment, so each cycle is reported at most
+/// once and memory stays bounded by the tokens fragmented within one window.
+pub(crate) struct Cycles {
+    clock: Arc<dyn Clock>,
+    window: Duration,
+    pending: Mutex<HashMap<(String, U256), Fragmented>>,
+}
+
+impl Cycles {
+    pub(crate) fn new(clock: Arc<dyn Clock>, window: Duration) -> Self {
+        Cycles {
+            clock,
+            window,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remember every token of a fragment event. A token fragmented again replaces its older
+    /// entry.
+    pub(crate) fn record(&self, msg: &Message) {
+        let now = self.clock.now();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, fragmented| now.duration_since(fragmented.at) < self.window);
+
+        for key in msg.token_keys() {
+            pending.insert(
+                key,
+                Fragmented {
+                    tx_hash: msg.tx_hash().to_string(),
+                    cost: msg.mu_token_cost(),
+                    at: now,
+                },
+            );
+        }
+    }
+
+    /// Take the fragment matching a redeemed token, if it happened within the window.
+    fn take(&self, key: &(String, U256)) -> Option<(Fragmented, Duration)> {
+        let now = self.clock.now();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, fragmented| now.duration_since(fragmented.at) < self.window);
+
+        pending.remove(key).map(|fragmented| {
+            let elapsed = now.duration_since(fragmented.at);
+            (fragmented, elapsed)
+        })
+    }
+}
+
+/// Poll for redemptions (`ClaimRandomNft`) and report every one that completes a tracked cycle,
+/// with the spread realized against the top bid at redemption time.
+pub(crate) async fn watch(client: Arc<Provider<Http>>, state: Arc<AppState>) -> eyre::Result<()> {
+    let Some(cycles) = &state.cycles else {
+        return Ok(());
+    };
+
+    let interval = Duration::from_millis(config::var_or("POLL_INTERVAL_MS", 12_000));
+    let chunk_size: u64 = config::var_or("POLL_CHUNK_BLOCKS", 1_000);
+    let mut last_processed = client.get_block_number().await?.as_u64();
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let head = match client.get_block_number().await {
+            Ok(head) => head.as_u64(),
+            Err(e) => {
+                error!("Error getting block number for cycles: {:?}", e);
+                continue;
+            }
+        };
+        if head <= last_processed {
+            continue;
+        }
+
+        let from = last_processed + 1;
+        let to = head.min(from + chunk_size - 1);
+
+        let logs = match Contract::event_of_type::<ClaimRandomNftFilter>(client.clone())
+            .address(ValueOrArray::Array(state.chain.flooring.clone()))
+            .from_block(from)
+            .to_block(to)
+            .query_with_meta()
+            .await
+        {
+            Ok(logs) => logs,
+            Err(e) => {
+                error!("Error querying redemptions {} to {}: {:?}", from, to, e);
+                continue;
+            }
+        };
+        last_processed = to;
+
+        for (log, meta) in logs {
+            let collection = format!("{:#x}", log.collection);
+
+            for token_id in log.token_ids {
+                let Some((fragmented, elapsed)) = cycles.take(&(collection.clone(), token_id))
+                else {
+                    continue;
+                };
+
+                let top_bid = Message::default()
+                    .get_top_bid(&collection, token_id)
+                    .await
+                    .map(|top_bid| top_bid.price());
+                let spread = match (&top_bid, fragmented.cost) {
+                    (Ok(top_bid), Some(cost)) => format!("{} ETH", top_bid - cost),
+                    _ => "unknown".to_string(),
+                };
+
+                let text = format!(
+                    "Cycle completed for {} token {} after {}\nFragmented: <a href=\"{}\">TX</a> -- Redeemed: <a href=\"{}\">TX</a>\nRealized spread vs top bid: {}",
+                    collection,
+                    token_id,
+                    crate::message::format_age(
+                        chrono::Duration::from_std(elapsed).unwrap_or_else(|_| chrono::Duration::zero())
+                    ),
+                    state.chain.tx_link(&fragmented.tx_hash),
+                    state.chain.tx_link(&format!("{:#x}", meta.transaction_hash)),
+                    spread,
+                );
+                info!("{}", text);
+                notifier::notify_all_text(&state.notifiers, &text).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use ethers::prelude::LogMeta;
+use ethers::types::U256;
+use tracing::{error, warn};
+
+use crate::clock::Clock;
+
+/// Where dedup keys live. Keys are
+///
+/// - `tx:{tx_hash}:{log_index}` for an event that was seen, kept for `DEDUP_TX_WINDOW_SECS`
+/// - `token:{collection}:{token_id}` for a token that was alerted on, kept for
+///   `DEDUP_TOKEN_WINDOW_SECS`
+///
+/// with the tx hash and collection as lowercase `0x` hex and the token id in decimal. A key
+/// expires once its TTL has passed.
+#[async_trait]
+pub(crate) trait DedupStore: Send + Sync {
+    /// Insert `key` unless it is already present. Returns true if it was inserted.
+    async fn insert_new(&self, key: &str, ttl: Duration) -> bool;
+
+    async fn contains(&self, key: &str) -> bool;
+
+    /// Insert `key`, restarting its TTL if it is already present.
+    async fn insert(&self, key: &str, ttl: Duration);
+}
+
+/// The store selected by `DEDUP_STORE`: `memory` (the default) for a single run, `file` to
+/// survive restarts of a single instance (`DEDUP_FILE`, default `dedup.json`), or `redis`
+/// (`REDIS_URL`) to share keys between instances, when built with the `redis` feature.
+pub(crate) fn store_from_env(clock: Arc<dyn Clock>) -> Box<dyn DedupStore> {
+    match dotenv::var("DEDUP_STORE").as_deref() {
+        Ok("file") => Box::new(FileStore::load(
+            dotenv::var("DEDUP_FILE")
+                .unwrap_or_else(|_| "dedup.json".to_string())
+                .into(),
+        )),
+        #[cfg(feature = "redis")]
+        Ok("redis") => Box::new(RedisStore::new(
+            &dotenv::var("REDIS_URL").expect("REDIS_URL must be set for DEDUP_STORE=redis"),
+        )),
+        Ok("memory") | Err(_) => Box::new(MemoryStore::new(clock)),
+        Ok(other) => {
+            warn!("Unsupported DEDUP_STORE {}, using memory", other);
+            Box::new(MemoryStore::new(clock))
+        }
+    }
+}
+
+/// Suppresses repeats, by event and by token. Each check has its own window, and a zero window
+/// turns it off.
+pub(crate) struct Dedup {
+    store: Box<dyn DedupStore>,
+    /// how long an event is remembered by `(tx_hash, log_index)`, e.g. to skip one delivered
+    /// again after a websocket reconnect
+    tx_window: Duration,
+    /// how long after an alert its tokens are left out of later events, whatever the transaction
+    token_window: Duration,
+}
+
+impl Dedup {
+    pub(crate) fn new(
+        store: Box<dyn DedupStore>,
+        tx_window: Duration,
+        token_window: Duration,
+    ) -> Self {
+        Dedup {
+            store,
+            tx_window,
+            token_window,
+        }
+    }
+
+    /// Returns true (and records the event) if it wasn't seen inside the window.
+    pub(crate) async fn first_seen(&self, meta: &LogMeta) -> bool {
+        if self.tx_window.is_zero() {
+            return true;
+        }
+
+        let key = format!("tx:{:#x}:{}", meta.transaction_hash, meta.log_index);
+        self.store.insert_new(&key, self.tx_window).await
+    }
+
+    /// Whether an alert including this token went out inside the window.
+    pub(crate) async fn recently_alerted(&self, collection: &str, token_id: U256) -> bool {
+        if self.token_window.is_zero() {
+            return false;
+        }
+
+        self.store.contains(&token_key(collection, token_id)).await
+    }
+
+    pub(crate) async fn record_alerted(&self, keys: Vec<(String, U256)>) {
+        if self.token_window.is_zero() {
+            return;
+        }
+
+        for (collection, token_id) in keys {
+            self.store
+                .insert(&token_key(&collection, token_id), self.token_window)
+                .await;
+        }
+    }
+}
+
+fn token_key(collection: &str, token_id: U256) -> String {
+    format!("token:{}:{}", collection.to_lowercase(), token_id)
+}
+
+/// Keys in memory, lost on restart.
+pub(crate) struct MemoryStore {
+    clock: Arc<dyn Clock>,
+    /// expiry of every key
+    keys: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryStore {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        MemoryStore {
+            clock,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The live keys, with expired ones dropped.
+    fn live(&self) -> (Instant, std::sync::MutexGuard<'_, HashMap<String, Instant>>) {
+        let now = self.clock.now();
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, expires_at| now < *expires_at);
+        (now, keys)
+    }
+}
+
+#[async_trait]
+impl DedupStore for MemoryStore {
+    async fn insert_new(&self, key: &str, ttl: Duration) -> bool {
+        let (now, mut keys) = self.live();
+        if keys.contains_key(key) {
+            return false;
+        }
+
+        keys.insert(key.to_string(), now + ttl);
+        true
+    }
+
+    async fn contains(&self, key: &str) -> bool {
+        self.live().1.contains_key(key)
+    }
+
+    async fn insert(&self, key: &str, ttl: Duration) {
+        let (now, mut keys) = self.live();
+        keys.insert(key.to_string(), now + ttl);
+    }
+}
+
+/// Keys persisted to a JSON file of `{key: expiry unix seconds}` after every insert, so they
+/// survive a restart. Only safe for a single instance.
+pub(crate) struct FileStore {
+    path: PathBuf,
+    keys: Mutex<HashMap<String, u64>>,
+}
+
+impl FileStore {
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let keys = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("Error reading dedup file: {:?}", e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        FileStore {
+            path,
+            keys: Mutex::new(keys),
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn persist(&self, keys: &HashMap<String, u64>) {
+        let json = serde_json::json!(keys);
+
+        if let Err(e) = std::fs::write(&self.path, json.to_string()) {
+            error!("Error writing dedup file: {:?}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl DedupStore for FileStore {
+    async fn insert_new(&self, key: &str, ttl: Duration) -> bool {
+        let now = FileStore::now();
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, expires_at| now < *expires_at);
+        if keys.contains_key(key) {
+            return false;
+        }
+
+        keys.insert(key.to_string(), now + ttl.as_secs());
+        self.persist(&keys);
+        true
+    }
+
+    async fn contains(&self, key: &str) -> bool {
+        let now = FileStore::now();
+        self.keys
+            .lock()
+            .unwrap()
+            .get(key)
+            .map_or(false, |expires_at| now < *expires_at)
+    }
+
+    async fn insert(&self, key: &str, ttl: Duration) {
+        let now = FileStore::now();
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, expires_at| now < *expires_at);
+        keys.insert(key.to_string(), now + ttl.as_secs());
+        self.persist(&keys);
+    }
+}
+
+/// Keys in Redis under `DEDUP_KEY_PREFIX` (default `floor_monitor:`), expiring with Redis' own
+/// TTLs, so every instance pointed at the same Redis shares them. Redis errors are logged and
+/// treated as a miss, so an outage means possible repeats rather than lost alerts.
+#[cfg(feature = "redis")]
+pub(crate) struct RedisStore {
+    client: redis::Client,
+    prefix: String,
+    connection: tokio::sync::OnceCell<redis::aio::MultiplexedConnection>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    pub(crate) fn new(url: &str) -> Self {
+        RedisStore {
+            client: redis::Client::open(url).expect("could not parse REDIS_URL"),
+            prefix: dotenv::var("DEDUP_KEY_PREFIX")
+                .unwrap_or_else(|_| "floor_monitor:".to_string()),
+            connection: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    async fn query<T: redis::FromRedisValue>(&self, cmd: &mut redis::Cmd) -> redis::RedisResult<T> {
+        let mut connection = self
+            .connection
+            .get_or_try_init(|| self.client.get_multiplexed_tokio_connection())
+            .await?
+            .clone();
+
+        cmd.query_async(&mut connection).await
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl DedupStore for RedisStore {
+    async fn insert_new(&self, key: &str, ttl: Duration) -> bool {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(format!("{}{}", self.prefix, key))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl.as_secs().max(1));
+
+        match self.query::<Option<String>>(&mut cmd).await {
+            Ok(set) => set.is_some(),
+            Err(e) => {
+                error!("Error checking dedup key in Redis: {:?}", e);
+                true
+            }
+        }
+    }
+
+    async fn contains(&self, key: &str) -> bool {
+        let mut cmd = redis::cmd("EXISTS");
+        cmd.arg(format!("{}{}", self.prefix, key));
+
+        self.query::<bool>(&mut cmd).await.unwrap_or_else(|e| {
+            error!("Error reading dedup key from Redis: {:?}", e);
+            false
+        })
+    }
+
+    async fn insert(&self, key: &str, ttl: Duration) {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(format!("{}{}", self.prefix, key))
+            .arg(1)
+            .arg("EX")
+            .arg(ttl.as_secs().max(1));
+
+        if let Err(e) = self.query::<()>(&mut cmd).await {
+            error!("Error writing dedup key to Redis: {:?}", e);
+        }
+    }
+}
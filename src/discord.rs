@@ -0,0 +1,60 @@
+use crate::message::Message;
+
+/// Discord rejects webhook messages with more embeds than this.
+pub(crate) const MAX_EMBEDS: usize = 10;
+
+/// Post `msg` to a Discord webhook, with one embed per token.
+pub(crate) async fn send(webhook_url: &str, msg: &Message) -> eyre::Result<()> {
+    post(webhook_url, &msg.discord_payload()).await
+}
+
+/// Post a plain text message to a Discord webhook. Notices are written as Telegram html, so
+/// links are rewritten to markdown.
+pub(crate) async fn send_text(webhook_url: &str, text: &str) -> eyre::Result<()> {
+    post(
+        webhook_url,
+        &serde_json::json!({ "content": html_links_to_markdown(text) }),
+    )
+    .await
+}
+
+/// Turn every `<a href="url">text</a>` into `[text](url)`.
+fn html_links_to_markdown(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<a href=\"") {
+        let Some((url, after_url)) = rest[start + 9..].split_once("\">") else {
+            break;
+        };
+        let Some((text, after_link)) = after_url.split_once("</a>") else {
+            break;
+        };
+
+        out.push_str(&rest[..start]);
+        out.push_str(&format!("[{}]({})", text.trim(), url));
+        rest = after_link;
+    }
+
+    out.push_str(rest);
+    out
+}
+
+async fn post(webhook_url: &str, payload: &serde_json::Value) -> eyre::Result<()> {
+    let res = crate::http::client()
+        .post(webhook_url)
+        .json(payload)
+        .send()
+        .await?;
+
+    let status = res.status();
+    if !status.is_success() {
+        eyre::bail!(
+            "discord returned {}: {}",
+            status,
+            res.text().await.unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
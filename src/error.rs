@@ -0,0 +1,292 @@
+use std::fmt::{Display, Formatter};
+
+/// The upstream service an error came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum ApiProvider {
+    ParaSwap,
+    Reservoir,
+    DeepNftValue,
+    Etherscan,
+    Rpc,
+    Blur,
+    TokenUri,
+    ValuationEndpoint,
+    DexScreener,
+}
+
+/// What kind of failure it was, independent of the provider.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum ErrorCategory {
+    Timeout,
+    Auth,
+    RateLimit,
+    Parse,
+    ServerError,
+    /// the call was skipped because the provider's circuit breaker is open
+    CircuitOpen,
+    Other,
+}
+
+impl Display for ApiProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ApiProvider::ParaSwap => "paraswap",
+            ApiProvider::Reservoir => "reservoir",
+            ApiProvider::DeepNftValue => "deepnftvalue",
+            ApiProvider::Etherscan => "etherscan",
+            ApiProvider::Rpc => "rpc",
+            ApiProvider::Blur => "blur",
+            ApiProvider::TokenUri => "token_uri",
+            ApiProvider::ValuationEndpoint => "valuation_endpoint",
+            ApiProvider::DexScreener => "dexscreener",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for ErrorCategory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Auth => "auth",
+            ErrorCategory::RateLimit => "rate_limit",
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::ServerError => "5xx",
+            ErrorCategory::CircuitOpen => "circuit_open",
+            ErrorCategory::Other => "other",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl ErrorCategory {
+    fn from_status(status: reqwest::StatusCode) -> Self {
+        match status.as_u16() {
+            401 | 403 => ErrorCategory::Auth,
+            429 => ErrorCategory::RateLimit,
+            500..=599 => ErrorCategory::ServerError,
+            _ => ErrorCategory::Other,
+        }
+    }
+}
+
+/// An error fetching data for a message, tagged with the provider and category it falls under.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MessageError {
+    #[error("{provider} request failed ({category}): {source}")]
+    Request {
+        provider: ApiProvider,
+        category: ErrorCategory,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("{provider} returned {status}")]
+    Status {
+        provider: ApiProvider,
+        status: reqwest::StatusCode,
+    },
+    /// anything but JSON, such as a gateway's html error page
+    #[error("{provider} returned {status} with {content_type} instead of JSON: {body}")]
+    UnexpectedResponse {
+        provider: ApiProvider,
+        status: reqwest::StatusCode,
+        content_type: String,
+        /// the start of the body, see `snippet`
+        body: String,
+    },
+    #[error("{provider} response could not be parsed: {message}")]
+    Parse {
+        provider: ApiProvider,
+        message: String,
+    },
+    #[error("{provider} had no usable data: {message}")]
+    NoData {
+        provider: ApiProvider,
+        message: String,
+    },
+    #[error("{provider} needs {var} to be set")]
+    MissingKey {
+        provider: ApiProvider,
+        var: &'static str,
+    },
+    #[error("{provider} call failed: {message}")]
+    Rpc {
+        provider: ApiProvider,
+        message: String,
+    },
+    #[error("{provider} skipped while its circuit breaker is open")]
+    CircuitOpen { provider: ApiProvider },
+    /// `collectionInfo` reported the zero address as the fragment token
+    #[error("{collection} has no MuToken, it is not fragmented on Flooring")]
+    NoMuToken { collection: String },
+}
+
+impl MessageError {
+    pub(crate) fn request(provider: ApiProvider, source: reqwest::Error) -> Self {
+        let category = if source.is_timeout() {
+            ErrorCategory::Timeout
+        } else if source.is_decode() {
+            ErrorCategory::Parse
+        } else if let Some(status) = source.status() {
+            ErrorCategory::from_status(status)
+        } else {
+            ErrorCategory::Other
+        };
+
+        MessageError::Request {
+            provider,
+            category,
+            source,
+        }
+    }
+
+    pub(crate) fn parse(provider: ApiProvider, message: impl Display) -> Self {
+        MessageError::Parse {
+            provider,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn no_data(provider: ApiProvider, message: impl Display) -> Self {
+        MessageError::NoData {
+            provider,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn rpc(message: impl Display) -> Self {
+        MessageError::Rpc {
+            provider: ApiProvider::Rpc,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn provider(&self) -> ApiProvider {
+        match self {
+            MessageError::Request { provider, .. }
+            | MessageError::Status { provider, .. }
+            | MessageError::UnexpectedResponse { provider, .. }
+            | MessageError::Parse { provider, .. }
+            | MessageError::NoData { provider, .. }
+            | MessageError::MissingKey { provider, .. }
+            | MessageError::Rpc { provider, .. }
+            | MessageError::CircuitOpen { provider } => *provider,
+            MessageError::NoMuToken { .. } => ApiProvider::Rpc,
+        }
+    }
+
+    pub(crate) fn category(&self) -> ErrorCategory {
+        match self {
+            MessageError::Request { category, .. } => *category,
+            MessageError::Status { status, .. } => ErrorCategory::from_status(*status),
+            MessageError::UnexpectedResponse { status, .. } if !status.is_success() => {
+                ErrorCategory::from_status(*status)
+            }
+            MessageError::UnexpectedResponse { .. } => ErrorCategory::Parse,
+            MessageError::Parse { .. } => ErrorCategory::Parse,
+            MessageError::MissingKey { .. } => ErrorCategory::Auth,
+            MessageError::CircuitOpen { .. } => ErrorCategory::CircuitOpen,
+            MessageError::Rpc { message, .. } if message.contains("timed out") => {
+                ErrorCategory::Timeout
+            }
+            MessageError::NoData { .. }
+            | MessageError::Rpc { .. }
+            | MessageError::NoMuToken { .. } => ErrorCategory::Other,
+        }
+    }
+
+    /// Whether this says the provider itself is unhealthy, as opposed to it answering that it
+    /// has nothing for this request.
+    fn is_provider_failure(&self) -> bool {
+        match self {
+            MessageError::Request { category, .. } => *category != ErrorCategory::Parse,
+            MessageError::Status { .. } => self.category() != ErrorCategory::Other,
+            // even with a success status, a page instead of JSON means the API isn't answering
+            MessageError::UnexpectedResponse { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Read the API key `provider` needs from `var`, as an error rather than a panic when unset.
+pub(crate) fn api_key(provider: ApiProvider, var: &'static str) -> Result<String, MessageError> {
+    dotenv::var(var).map_err(|_| MessageError::MissingKey { provider, var })
+}
+
+/// GET `req` and decode the JSON body, treating any non-success status as an error. Calls to a
+/// provider whose circuit breaker is open are skipped, and the rest wait their turn for an
+/// `API_CONCURRENCY` permit.
+pub(crate) async fn get_json(
+    provider: ApiProvider,
+    req: reqwest::RequestBuilder,
+) -> Result<serde_json::Value, MessageError> {
+    let breakers = crate::breaker::breakers();
+    if !breakers.allow(provider) {
+        return Err(MessageError::CircuitOpen { provider });
+    }
+
+    let permit = match crate::fair::limiter() {
+        Some(limiter) => Some(limiter.acquire().await),
+        None => None,
+    };
+    let result = send_json(provider, req).await;
+    drop(permit);
+
+    breakers.record(
+        provider,
+        !result
+            .as_ref()
+            .is_err_and(MessageError::is_provider_failure),
+    );
+
+    result
+}
+
+async fn send_json(
+    provider: ApiProvider,
+    req: reqwest::RequestBuilder,
+) -> Result<serde_json::Value, MessageError> {
+    let res = req
+        .send()
+        .await
+        .map_err(|e| MessageError::request(provider, e))?;
+
+    let status = res.status();
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(str::to_string);
+
+    // without a content type, parsing is the only way to find out
+    if let Some(content_type) = content_type.filter(|content_type| !content_type.contains("json")) {
+        let body = res.text().await.unwrap_or_default();
+        return Err(MessageError::UnexpectedResponse {
+            provider,
+            status,
+            content_type,
+            body: snippet(&body),
+        });
+    }
+
+    if !status.is_success() {
+        return Err(MessageError::Status { provider, status });
+    }
+
+    res.json::<serde_json::Value>()
+        .await
+        .map_err(|e| MessageError::request(provider, e))
+}
+
+/// The start of `body` on one line, enough to tell which error page it is.
+fn snippet(body: &str) -> String {
+    const MAX_CHARS: usize = 200;
+
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match body.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body,
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use tokio::sync::oneshot;
+
+tokio::task_local! {
+    /// The event the current task is processing, so API calls can be scheduled per event.
+    pub(crate) static EVENT: u64;
+}
+
+/// Permits for API requests, shared by every event, from `API_CONCURRENCY`. `None` (the
+/// default, 0) leaves requests unlimited.
+///
+/// When every permit is taken, requests queue per event and a freed permit goes to the next
+/// event in round robin order, not to whichever request asked first. An event with fifty tokens
+/// then gets one request through for every one of a single token event running next to it,
+/// rather than holding every permit until its tokens are done. Requests outside an event, such
+/// as commands, share one queue of their own.
+pub(crate) fn limiter() -> Option<&'static Arc<FairLimiter>> {
+    static LIMITER: OnceLock<Option<Arc<FairLimiter>>> = OnceLock::new();
+
+    LIMITER
+        .get_or_init(|| match crate::config::var_or("API_CONCURRENCY", 0usize) {
+            0 => None,
+            permits => Some(Arc::new(FairLimiter::new(permits))),
+        })
+        .as_ref()
+}
+
+pub(crate) struct FairLimiter {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    available: usize,
+    /// waiting requests by event, in the order events get their next turn
+    waiting: VecDeque<(u64, VecDeque<oneshot::Sender<Permit>>)>,
+}
+
+/// A request's permit, handed on to the next waiting event when dropped.
+pub(crate) struct Permit {
+    limiter: Arc<FairLimiter>,
+}
+
+impl FairLimiter {
+    fn new(permits: usize) -> Self {
+        FairLimiter {
+            inner: Mutex::new(Inner {
+                available: permits,
+                waiting: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Wait for a permit for a request made while processing the current event.
+    pub(crate) async fn acquire(self: &Arc<Self>) -> Permit {
+        let event = EVENT.try_with(|event| *event).unwrap_or(0);
+
+        let rx = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.available > 0 && inner.waiting.is_empty() {
+                inner.available -= 1;
+                return Permit {
+                    limiter: self.clone(),
+                };
+            }
+
+            let (tx, rx) = oneshot::channel();
+            match inner
+                .waiting
+                .iter_mut()
+                .find(|(waiting, _)| *waiting == event)
+            {
+                Some((_, queue)) => queue.push_back(tx),
+                None => inner.waiting.push_back((event, VecDeque::from([tx]))),
+            }
+            rx
+        };
+
+        // the sender is only dropped along with a permit sent through it
+        rx.await.expect("a waiting request is always sent a permit")
+    }
+
+    fn release(self: &Arc<Self>) {
+        let next = {
+            let mut inner = self.inner.lock().unwrap();
+            let Some((event, mut queue)) = inner.waiting.pop_front() else {
+                inner.available += 1;
+                return;
+            };
+
+            let next = queue.pop_front();
+            // to the back of the line until its other requests get a turn
+            if !queue.is_empty() {
+                inner.waiting.push_back((event, queue));
+            }
+            next
+        };
+
+        // a request that stopped waiting drops the permit, which releases it again
+        if let Some(next) = next {
+            let _ = next.send(Permit {
+                limiter: self.clone(),
+            });
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
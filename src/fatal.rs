@@ -0,0 +1,40 @@
+use std::process::ExitCode;
+
+/// Why the monitor stopped, each with its own exit code so a supervisor can tell a problem a
+/// restart may fix from one that needs a human.
+///
+/// | code | reason                                                          | restart? |
+/// |------|-----------------------------------------------------------------|----------|
+/// | 0    | clean exit, e.g. after `--once` or a shutdown signal            | -        |
+/// | 1    | anything else, including a failed `--selftest`                  | maybe    |
+/// | 2    | missing or invalid config                                       | no       |
+/// | 3    | an RPC endpoint unreachable after `RPC_STARTUP_RETRIES` retries | yes      |
+/// | 4    | a notifier can't deliver, e.g. a bad Telegram token             | no       |
+/// | 5    | an event source failed while running, e.g. a dropped websocket  | yes      |
+///
+/// A panic exits with Rust's own code, 101.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Fatal {
+    #[error("invalid config: {0}")]
+    Config(String),
+    #[error("RPC unreachable: {0}")]
+    Rpc(String),
+    #[error("notifier can't deliver alerts: {0}")]
+    Notifier(String),
+    #[error("event source failed: {0}")]
+    Source(String),
+    #[error(transparent)]
+    Other(#[from] eyre::Report),
+}
+
+impl Fatal {
+    pub(crate) fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Fatal::Other(_) => 1,
+            Fatal::Config(_) => 2,
+            Fatal::Rpc(_) => 3,
+            Fatal::Notifier(_) => 4,
+            Fatal::Source(_) => 5,
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::clock::Clock;
+use crate::message::format_age;
+use crate::notifier;
+use crate::state::AppState;
+
+/// When the last event was processed, to send a notice after every `interval` without one. This
+/// tells a quiet market apart from a broken monitor, unlike the websocket keepalive, which only
+/// keeps the connection up.
+pub(crate) struct Heartbeat {
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    /// names of the notifiers the notices go to, all of them when empty
+    notifiers: Vec<String>,
+    /// (last event or startup, next notice due)
+    times: Mutex<(Instant, Instant)>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(clock: Arc<dyn Clock>, interval: Duration, notifiers: Vec<String>) -> Self {
+        let now = clock.now();
+
+        Heartbeat {
+            clock,
+            interval,
+            notifiers,
+            times: Mutex::new((now, now + interval)),
+        }
+    }
+
+    /// Note that an event was processed, pushing the next notice back a whole interval.
+    pub(crate) fn seen(&self) {
+        let now = self.clock.now();
+        *self.times.lock().unwrap() = (now, now + self.interval);
+    }
+
+    /// How long it has been since the last event, if a notice is due.
+    fn take_due(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        let mut times = self.times.lock().unwrap();
+        let (last_event, next_notice) = &mut *times;
+
+        if now < *next_notice {
+            return None;
+        }
+
+        *next_notice = now + self.interval;
+        Some(now.duration_since(*last_event))
+    }
+}
+
+/// Send a low priority notice to the `HEARTBEAT_NOTIFIERS` every `HEARTBEAT_HOURS` in which no
+/// event was processed.
+pub(crate) async fn watch(state: Arc<AppState>) {
+    let Some(heartbeat) = &state.heartbeat else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        let Some(quiet_for) = heartbeat.take_due() else {
+            continue;
+        };
+        let quiet_for =
+            chrono::Duration::from_std(quiet_for).unwrap_or_else(|_| chrono::Duration::zero());
+
+        let chain = if state.multi_chain {
+            format!(" on {}", state.chain.name)
+        } else {
+            String::new()
+        };
+        let text = format!(
+            "Still alive, {} since the last event{}",
+            format_age(quiet_for),
+            chain
+        );
+        info!("{}", text);
+
+        notifier::notify_low_priority(&state.notifiers, &heartbeat.notifiers, &text).await;
+    }
+}
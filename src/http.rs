@@ -0,0 +1,60 @@
+use std::sync::OnceLock;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tracing::warn;
+
+/// The client for every API call: Reservoir, DeepNFTValue, Blur, Etherscan, DexScreener, the
+/// valuation endpoint, token metadata and the Discord webhook. It sends `API_HEADERS` and goes
+/// through `PROXY_URL`. Telegram goes through teloxide's own client and takes neither.
+pub(crate) fn client() -> reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+    CLIENT
+        .get_or_init(|| {
+            builder("API_HEADERS")
+                .build()
+                .expect("could not build HTTP client")
+        })
+        .clone()
+}
+
+/// A client builder sending the headers in `headers_key`, as `;` separated `Name: value`
+/// entries, and going through `PROXY_URL` when set. The HTTP RPC provider uses `RPC_HEADERS`, so
+/// provider credentials aren't sent to third party APIs. Websocket connections take neither.
+pub(crate) fn builder(headers_key: &str) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder().default_headers(headers(headers_key));
+
+    if let Ok(proxy_url) = dotenv::var("PROXY_URL") {
+        match reqwest::Proxy::all(&proxy_url) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(e) => warn!("Ignoring PROXY_URL: {:?}", e),
+        }
+    }
+
+    builder
+}
+
+fn headers(key: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let Ok(value) = dotenv::var(key) else {
+        return headers;
+    };
+
+    for entry in value.split(';').filter(|entry| !entry.trim().is_empty()) {
+        let parsed = entry.split_once(':').and_then(|(name, value)| {
+            Some((
+                HeaderName::from_bytes(name.trim().as_bytes()).ok()?,
+                HeaderValue::from_str(value.trim()).ok()?,
+            ))
+        });
+
+        match parsed {
+            Some((name, value)) => {
+                headers.insert(name, value);
+            }
+            None => warn!("Ignoring malformed {key} entry"),
+        }
+    }
+
+    headers
+}
@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+use tracing::{error, info};
+
+use crate::clock::Clock;
+use crate::message::parse_decimal;
+
+/// Paper trading ledger: the hypothetical profit of every alert that was sent.
+pub(crate) struct Ledger {
+    clock: Arc<dyn Clock>,
+    /// where the totals are persisted, if anywhere
+    path: Option<PathBuf>,
+    /// how often the ledger resets itself, if ever
+    reset_interval: Option<Duration>,
+    state: Mutex<LedgerState>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct LedgerState {
+    pub(crate) total_profit: Decimal,
+    pub(crate) alerts: u64,
+    pub(crate) since: Instant,
+}
+
+impl Ledger {
+    pub(crate) fn new(
+        clock: Arc<dyn Clock>,
+        path: Option<PathBuf>,
+        reset_interval: Option<Duration>,
+    ) -> Self {
+        let mut state = LedgerState {
+            total_profit: Decimal::ZERO,
+            alerts: 0,
+            since: clock.now(),
+        };
+
+        // pick up where the last run left off
+        if let Some(path) = &path {
+            if let Ok(contents) = std::fs::read_to_string(path) {
+                match serde_json::from_str::<serde_json::Value>(&contents) {
+                    Ok(json) => {
+                        // older ledgers stored the total as a float
+                        state.total_profit =
+                            parse_decimal(&json["total_profit"]).unwrap_or_default();
+                        state.alerts = json["alerts"].as_u64().unwrap_or_default();
+                    }
+                    Err(e) => error!("Error reading ledger file: {:?}", e),
+                }
+            }
+        }
+
+        Ledger {
+            clock,
+            path,
+            reset_interval,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Add a sent alert's profit to the ledger.
+    pub(crate) fn record(&self, profit: Decimal) {
+        let mut state = self.state.lock().unwrap();
+        self.reset_if_due(&mut state);
+
+        state.total_profit += profit;
+        state.alerts += 1;
+
+        self.persist(&state);
+    }
+
+    pub(crate) fn snapshot(&self) -> LedgerState {
+        let mut state = self.state.lock().unwrap();
+        self.reset_if_due(&mut state);
+
+        state.clone()
+    }
+
+    pub(crate) fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        Self::clear(&mut state, self.clock.now());
+
+        self.persist(&state);
+    }
+
+    fn reset_if_due(&self, state: &mut LedgerState) {
+        if let Some(interval) = self.reset_interval {
+            let now = self.clock.now();
+            if now.duration_since(state.since) >= interval {
+                info!(
+                    "Resetting ledger after {} alerts with {} ETH profit",
+                    state.alerts, state.total_profit
+                );
+                Self::clear(state, now);
+                self.persist(state);
+            }
+        }
+    }
+
+    fn clear(state: &mut LedgerState, now: Instant) {
+        state.total_profit = Decimal::ZERO;
+        state.alerts = 0;
+        state.since = now;
+    }
+
+    fn persist(&self, state: &LedgerState) {
+        if let Some(path) = &self.path {
+            let json = serde_json::json!({
+                "total_profit": state.total_profit.to_string(),
+                "alerts": state.alerts,
+            });
+
+            if let Err(e) = std::fs::write(path, json.to_string()) {
+                error!("Error writing ledger file: {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use tracing::warn;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+use crate::config;
+
+/// Install the global subscriber. Logs go to stdout unless `LOG_STDOUT=false`, and to a file
+/// rotated per `LOG_ROTATION` when `LOG_FILE` is set. The returned guard flushes buffered file
+/// output when dropped, so it has to live until the process exits.
+pub(crate) fn init() -> Option<WorkerGuard> {
+    let level = config::var_or("LOG_LEVEL", LevelFilter::INFO);
+    let rotation = dotenv::var("LOG_ROTATION").unwrap_or_else(|_| "daily".to_string());
+
+    let mut file_error = None;
+    let (writer, guard) =
+        match dotenv::var("LOG_FILE").map(|path| (appender(&path, &rotation), path)) {
+            Ok((Ok(appender), _)) => {
+                let (writer, guard) = tracing_appender::non_blocking(appender);
+                (Some(writer), Some(guard))
+            }
+            Ok((Err(e), path)) => {
+                file_error = Some((path, e));
+                (None, None)
+            }
+            Err(_) => (None, None),
+        };
+
+    // never leave the process without any log output
+    let stdout = (writer.is_none() || config::var_or("LOG_STDOUT", true)).then(fmt::layer);
+
+    tracing_subscriber::registry()
+        .with(level)
+        .with(stdout)
+        .with(writer.map(|writer| fmt::layer().with_ansi(false).with_writer(writer)))
+        .init();
+
+    // only reportable once the subscriber is installed
+    if let Some((path, e)) = file_error {
+        warn!(
+            "Could not open log file {}, logging to stdout only: {}",
+            path, e
+        );
+    }
+
+    guard
+}
+
+fn appender(path: &str, rotation: &str) -> eyre::Result<RollingFileAppender> {
+    let path = Path::new(path);
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let prefix = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| eyre::eyre!("no file name in {}", path.display()))?;
+
+    let rotation = match rotation {
+        "hourly" => Rotation::HOURLY,
+        "minutely" => Rotation::MINUTELY,
+        "never" => Rotation::NEVER,
+        "daily" => Rotation::DAILY,
+        other => eyre::bail!("unknown LOG_ROTATION {}", other),
+    };
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(prefix);
+
+    // older files beyond this count are deleted on rotation
+    if let Some(max_files) = config::var_opt::<usize>("LOG_MAX_FILES") {
+        builder = builder.max_log_files(max_files);
+    }
+
+    Ok(builder.build(dir)?)
+}
@@ -1,103 +1,992 @@
 use ethers::{
     contract::{abigen, Contract},
-    core::types::ValueOrArray,
+    core::types::{Address, ValueOrArray, H256},
     prelude::LogMeta,
-    providers::{Http, Provider, StreamExt, Ws},
+    providers::{Http, Middleware, Provider, StreamExt, Ws},
 };
+use futures::FutureExt;
+use rust_decimal::Decimal;
 use teloxide::prelude::*;
+use tracing::{debug, error, info, warn};
 
-use std::{error::Error, sync::Arc};
+use std::{
+    panic::AssertUnwindSafe, process::ExitCode, sync::atomic::Ordering, sync::Arc, time::Duration,
+};
+use tokio::sync::mpsc::Sender;
+
+use crate::chain::ChainConfig;
+use crate::fatal::Fatal;
+use crate::state::AppState;
 
+pub mod blur;
+pub mod breaker;
+pub mod batch;
+pub mod chain;
+pub mod checkpoint;
+pub mod clock;
+pub mod commands;
+pub mod config;
+pub mod cooldown;
+pub mod csv_sink;
+pub mod cycles;
+pub mod dedup;
+pub mod discord;
+pub mod error;
+pub mod fair;
+pub mod fatal;
+pub mod heartbeat;
+pub mod http;
+pub mod ledger;
+pub mod logging;
 pub mod message;
+pub mod metadata;
+pub mod metrics;
+pub mod mute;
+pub mod notifier;
+pub mod poll;
+pub mod price_moves;
+pub mod quiet_hours;
+pub mod replay;
+pub mod selftest;
+pub mod sent_alerts;
+pub mod state;
+pub mod ttl_cache;
+pub mod valuation_endpoint;
 
 abigen!(
     FlooringInterface,
     r#"[
         event FragmentNft(address indexed operator, address indexed onBehalfOf, address indexed collection, uint256[] tokenIds)
+        event ClaimRandomNft(address indexed operator, address indexed receiver, address indexed collection, uint256[] tokenIds, uint256 creditCost)
         function collectionInfo(address collection) external view returns (address fragmentToken, uint256 freeNftLength, uint64 lastUpdatedBucket, uint64 nextKeyId, uint64 activeSafeBoxCnt, uint64 infiniteCnt, uint64 nextActivityId)
+        struct SafeBox { uint64 keyId; uint32 expiryTs; address owner; }
+        function getSafeBox(address collection, uint256 nftId) external view returns (SafeBox memory)
     ]"#,
 );
 
-const FLOORING: &str = "0x3eb879cc9a0Ef4C6f1d870A40ae187768c278Da2";
+abigen!(
+    FragmentToken,
+    r#"[
+        function totalSupply() external view returns (uint256)
+        function symbol() external view returns (string)
+    ]"#,
+);
+
+abigen!(
+    Erc721,
+    r#"[
+        function ownerOf(uint256 tokenId) external view returns (address)
+        function tokenURI(uint256 tokenId) external view returns (string)
+    ]"#,
+);
+
+/// The contract answering `collectionInfo`, shared by everything that needs to read it.
+pub(crate) type Flooring = FlooringInterface<Provider<Http>>;
 
 /// Subscribe to a typed event stream without requiring a `Contract` instance.
 /// In this example we subscribe Chainlink price feeds and filter out them
 /// by address.
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let client = get_wss_client().await;
-    let client = Arc::new(client);
+async fn main() -> ExitCode {
+    // load a specific env file before anything reads config; values in it take precedence
+    // over the default `.env`, which dotenv never lets override a variable already set
+    let env_file = env_file();
+    if let Some(path) = &env_file {
+        if let Err(e) = dotenv::from_filename(path) {
+            let fatal = Fatal::Config(format!("could not load env file {}: {}", path, e));
+            eprintln!("{}", fatal);
+            return fatal.exit_code();
+        }
+    }
 
-    // Build an Event by type. We are not tied to a contract instance. We use builder functions to
-    // refine the event filter
-    let mut event = Contract::event_of_type::<FragmentNftFilter>(client)
-        .address(ValueOrArray::Array(vec![FLOORING.parse()?]));
+    // held until exit so buffered file output is flushed
+    let _log_guard = logging::init();
+    if let Some(path) = env_file {
+        info!("Loaded config from {}", path);
+    }
 
-    match dotenv::var("STARTING_BLOCK")
-        .unwrap()
-        .parse::<u64>()
-        .unwrap()
-    {
-        0 => {
-            println!("Starting from latest block");
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("Exiting: {}", e);
+            e.exit_code()
         }
-        block => {
-            println!("Starting from block {}", block);
-            event = event.from_block(block);
+    }
+}
+
+/// Monitor until the event stream ends or a shutdown signal, with the reason for stopping early
+/// as a `Fatal`.
+async fn run() -> Result<(), Fatal> {
+    let chains = ChainConfig::from_env()?;
+    check_config(&chains)?;
+
+    // check every configured key and endpoint, then exit without monitoring
+    if std::env::args().any(|arg| arg == "--selftest") {
+        let ok = selftest::run(&get_http_client(&chains[0].http_rpc).await).await;
+        return match ok {
+            true => Ok(()),
+            false => Err(Fatal::Other(eyre::eyre!("selftest failed"))),
+        };
+    }
+
+    // one state per chain, each with its own HTTP provider and contract instance for every
+    // `collectionInfo` call, sharing the notifiers, ledger and metrics
+    let multi_chain = chains.len() > 1;
+    let mut states: Vec<Arc<AppState>> = Vec::new();
+    for chain in chains {
+        let client = get_http_client(&chain.http_rpc).await;
+        check_rpc(&chain.name, &client).await?;
+        let flooring = Flooring::new(chain.flooring_info, Arc::new(client));
+        let state = match states.first() {
+            Some(first) => first.for_chain(chain, flooring),
+            None => AppState {
+                multi_chain,
+                ..AppState::from_env(chain, flooring)
+            },
+        };
+        states.push(Arc::new(state));
+    }
+
+    // fail now rather than on every alert if, say, the bot can't post in the channel
+    notifier::check_access(&states[0].notifiers).await?;
+
+    // process a single event and exit, for smoke tests and cron style runs
+    let once = std::env::args().any(|arg| arg == "--once") || config::var_or("PROCESS_ONCE", false);
+
+    // events come either from the websocket subscriptions, from polling over HTTP as a fallback,
+    // or from a file recorded earlier for replay testing. Each is tagged with the index of the
+    // chain it came from.
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(100);
+    let mut sources = Vec::new();
+    for (index, state) in states.iter().enumerate() {
+        let (chain_tx, mut chain_rx) = tokio::sync::mpsc::channel(100);
+        let chain = &state.chain;
+
+        if let Ok(path) = dotenv::var("REPLAY_FILE") {
+            // recordings don't say which chain an event came from, so replay them all as the
+            // first chain
+            if index == 0 {
+                info!("Replaying events from {}", path);
+                sources.push(tokio::spawn(replay::run(path, chain_tx)));
+            }
+        } else {
+            let resume_from = state.checkpoint.resume_from();
+            let starting_block = chain.starting_block.or(resume_from.map(|(block, _)| block));
+            match (chain.starting_block, resume_from) {
+                (Some(block), _) => info!("Starting {} from block {}", chain.name, block),
+                (None, Some((block, log_index))) => info!(
+                    "Resuming {} from block {} after log {}",
+                    chain.name, block, log_index
+                ),
+                (None, None) => info!("Starting {} from latest block", chain.name),
+            }
+
+            if config::var_or("POLL_MODE", false) {
+                let client = Arc::new(get_http_client(&chain.http_rpc).await);
+                sources.push(tokio::spawn(poll::run(
+                    client,
+                    chain.flooring.clone(),
+                    chain.collection_topics(),
+                    starting_block,
+                    chain_tx,
+                )));
+            } else {
+                let client = Arc::new(get_wss_client(&chain.name, &chain.wss_rpc).await?);
+                sources.push(tokio::spawn(subscribe(
+                    client,
+                    chain.flooring.clone(),
+                    chain.collection_topics(),
+                    starting_block,
+                    chain_tx,
+                )));
+            }
+        }
+
+        let events_tx = events_tx.clone();
+        tokio::spawn(async move {
+            while let Some((log, meta)) = chain_rx.recv().await {
+                if events_tx.send((index, log, meta)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    // the stream ends once every chain's source has
+    drop(events_tx);
+
+    let monitored_collections = config::collections("MONITORED_COLLECTIONS");
+
+    // resolve slugs up front so the first alert per collection isn't slowed down by it
+    if !monitored_collections.is_empty() {
+        for state in &states {
+            message::Message::resolve_slugs(state, &monitored_collections).await;
         }
     }
 
-    let mut stream = event.subscribe_with_meta().await?;
+    // dump every event as it arrives so it can be replayed later
+    let recorder = match dotenv::var("RECORD_FILE") {
+        Ok(path) => Some(
+            replay::Recorder::create(path.as_ref())
+                .map_err(|e| Fatal::Config(format!("could not open RECORD_FILE: {}", e)))?,
+        ),
+        Err(_) => None,
+    };
+
+    // metrics and commands only touch shared state, or the first chain for /quote
+    if let Ok(addr) = dotenv::var("METRICS_ADDR") {
+        let state = states[0].clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr, state).await {
+                error!("Error serving metrics: {:?}", e);
+            }
+        });
+    }
+
+    for state in &states {
+        if state.batch.is_some() {
+            tokio::spawn(flush_batches(state.clone()));
+        }
+
+        if state.quiet_hours.is_some() {
+            tokio::spawn(flush_quiet_hours(state.clone()));
+        }
+
+        if state.price_moves.is_some() {
+            tokio::spawn(price_moves::watch(
+                state.clone(),
+                monitored_collections.clone(),
+            ));
+        }
+
+        if state.heartbeat.is_some() {
+            tokio::spawn(heartbeat::watch(state.clone()));
+        }
+
+        if state.cycles.is_some() {
+            let client = Arc::new(get_http_client(&state.chain.http_rpc).await);
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = cycles::watch(client, state).await {
+                    error!("Error watching redemptions: {:?}", e);
+                }
+            });
+        }
+    }
+
+    if config::var_or("ENABLE_COMMANDS", false) {
+        let bot = Bot::new(dotenv::var("TELEGRAM_BOT_TOKEN").unwrap());
+        tokio::spawn(commands::run(bot, states[0].clone()));
+    }
+
+    // stop taking events on Ctrl-C or SIGTERM, but let the one in progress finish
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    // each event is processed in its own task holding a permit, so at the cap new events wait in
+    // the channel rather than piling up in memory. The default of one keeps events in order.
+    let max_in_flight = config::var_or("MAX_CONCURRENT_EVENTS", 1usize).max(1);
+    let in_flight = Arc::new(tokio::sync::Semaphore::new(max_in_flight));
+    // ids for sharing API permits fairly between events, 0 is for requests outside an event
+    let mut event_id = 0;
 
     // Note that `log` has type FragmentNftUpdateFilter
-    while let Some(Ok((log, meta))) = stream.next().await {
+    loop {
+        let (index, log, meta) = tokio::select! {
+            event = events_rx.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = &mut shutdown => {
+                info!("Shutting down, no longer accepting events");
+                wait_in_flight(&in_flight, max_in_flight).await;
+                drain(&states).await;
+                info!("{}", states[0].metrics.error_summary());
+                return Ok(());
+            }
+        };
+        let state = &states[index];
+
+        // the resumed block can hold events the previous run already handled
+        if state.checkpoint.handled(&meta) {
+            debug!(
+                "Skipping event at or before the checkpoint: {}",
+                message::raw_log(&log, &meta)
+            );
+            continue;
+        }
+
         // send the log to telegram
-        println!("log: {:?}", log);
-        println!("meta: {:?}", meta);
+        info!(
+            "Fragment event on {}: {}",
+            state.chain.name,
+            message::raw_log(&log, &meta)
+        );
+        debug!("log: {:?}", log);
+        debug!("meta: {:?}", meta);
+
+        if let Some(recorder) = &recorder {
+            recorder.record(&log, &meta);
+        }
 
-        send_to_telegram(log, meta).await;
+        let permit = in_flight
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the event semaphore is never closed");
+        let task_state = state.clone();
+        event_id += 1;
+        state.checkpoint.start(&meta);
+        let task = tokio::spawn(fair::EVENT.scope(event_id, async move {
+            let metrics = &task_state.metrics;
+            metrics.events_in_flight.fetch_add(1, Ordering::Relaxed);
+            let processed = AssertUnwindSafe(send_to_telegram(log, meta.clone(), &task_state))
+                .catch_unwind()
+                .await;
+            task_state.checkpoint.finish(&meta, processed.is_ok());
+            if let Some(heartbeat) = &task_state.heartbeat {
+                heartbeat.seen();
+            }
+            metrics.events_in_flight.fetch_sub(1, Ordering::Relaxed);
+            drop(permit);
+
+            // a panicking event still fails its task
+            if let Err(panic) = processed {
+                std::panic::resume_unwind(panic);
+            }
+        }));
+
+        if once {
+            if let Err(e) = task.await {
+                error!("Error processing event: {:?}", e);
+            }
+            info!("Processed one event, exiting");
+            for state in &states {
+                flush_pending(state).await;
+            }
+            info!("{}", state.metrics.error_summary());
+            return Ok(());
+        }
+    }
+
+    info!("Event stream ended");
+    wait_in_flight(&in_flight, max_in_flight).await;
+    for state in &states {
+        flush_pending(state).await;
+    }
+    info!("{}", states[0].metrics.error_summary());
+
+    for source in sources {
+        source
+            .await
+            .map_err(|e| Fatal::Source(e.to_string()))?
+            .map_err(|e| Fatal::Source(format!("{:?}", e)))?;
     }
 
     Ok(())
 }
 
-async fn get_wss_client() -> Provider<Ws> {
-    Provider::<Ws>::connect(dotenv::var("WSS_RPC").unwrap())
+/// Fail at startup on config every alert needs, rather than panicking on the first event.
+fn check_config(chains: &[ChainConfig]) -> Result<(), Fatal> {
+    if dotenv::var("TELEGRAM_BOT_TOKEN").is_err() {
+        return Err(Fatal::Config("TELEGRAM_BOT_TOKEN must be set".to_string()));
+    }
+
+    if config::var_opt::<Decimal>("MINIMUM_PROFIT").is_none() {
+        return Err(Fatal::Config(
+            "MINIMUM_PROFIT must be set to a number".to_string(),
+        ));
+    }
+
+    if let Some(chain) = chains.iter().find(|chain| chain.http_rpc.is_empty()) {
+        return Err(Fatal::Config(format!("no HTTP RPC set for {}", chain.name)));
+    }
+
+    Ok(())
+}
+
+/// Make sure a chain's HTTP RPC answers, retrying `RPC_STARTUP_RETRIES` times two seconds apart.
+async fn check_rpc(chain: &str, client: &Provider<Http>) -> Result<(), Fatal> {
+    let retries: u32 = config::var_or("RPC_STARTUP_RETRIES", 3);
+    let mut attempt = 0;
+
+    loop {
+        match client.get_block_number().await {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                warn!(
+                    "{} HTTP RPC unreachable, retrying ({} of {}): {:?}",
+                    chain, attempt, retries, e
+                );
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+            Err(e) => return Err(Fatal::Rpc(format!("{} HTTP RPC: {}", chain, e))),
+        }
+    }
+}
+
+/// The env file named by `--env-file <path>`, or else by the `ENV_FILE` variable from the real
+/// environment.
+fn env_file() -> Option<String> {
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        if arg == "--env-file" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--env-file=") {
+            return Some(path.to_string());
+        }
+    }
+
+    std::env::var("ENV_FILE").ok()
+}
+
+/// Forward events from the websocket subscription until the monitor stops taking them. The
+/// subscription failing or ending is an error, so the monitor exits to be restarted.
+async fn subscribe(
+    client: Arc<Provider<Ws>>,
+    flooring: Vec<Address>,
+    collection_topics: Option<Vec<H256>>,
+    starting_block: Option<u64>,
+    events: Sender<(FragmentNftFilter, LogMeta)>,
+) -> eyre::Result<()> {
+    // some providers drop websockets that stay idle between events, so keep a little traffic on it
+    let keepalive = match config::var_or("WS_KEEPALIVE_SECS", 30) {
+        0 => None,
+        secs => Some(tokio::spawn(keepalive(
+            client.clone(),
+            Duration::from_secs(secs),
+        ))),
+    };
+
+    // Build an Event by type. We are not tied to a contract instance. We use builder functions to
+    // refine the event filter
+    let filter = |from_block: Option<u64>| {
+        let mut event = Contract::event_of_type::<FragmentNftFilter>(client.clone())
+            .address(ValueOrArray::Array(flooring.clone()));
+
+        // only the allowlisted collections, when the node can filter them for us
+        if let Some(topics) = &collection_topics {
+            event = event.topic3(ValueOrArray::Array(topics.clone()));
+        }
+
+        if let Some(block) = from_block {
+            event = event.from_block(block);
+        }
+
+        event
+    };
+
+    let event = filter(starting_block);
+    let subscribed = event.subscribe_with_meta().await;
+    // the streams borrow their filter, so the fallback one has to outlive `stream`
+    let fallback;
+    let mut stream = match (subscribed, starting_block) {
+        (Ok(stream), _) => stream,
+        // resuming from a block a pruned node no longer has
+        (Err(e), Some(block)) if poll::is_pruned(&e) => {
+            fallback = filter(Some(poll::fall_back(&client, &flooring, block, e).await?));
+            fallback.subscribe_with_meta().await?
+        }
+        (Err(e), _) => return Err(e.into()),
+    };
+
+    let result = loop {
+        match stream.next().await {
+            Some(Ok((log, meta))) => {
+                // the receiver is gone, the monitor is shutting down
+                if events.send((log, meta)).await.is_err() {
+                    break Ok(());
+                }
+            }
+            Some(Err(e)) => break Err(eyre::eyre!("websocket subscription failed: {}", e)),
+            None => break Err(eyre::eyre!("websocket subscription ended")),
+        }
+    };
+
+    if let Some(keepalive) = keepalive {
+        keepalive.abort();
+    }
+
+    result
+}
+
+/// Ask for the block number every `interval` so the websocket never looks idle.
+async fn keepalive(client: Arc<Provider<Ws>>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // the first tick completes immediately
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        if let Err(e) = client.get_block_number().await {
+            warn!("Websocket keepalive failed: {:?}", e);
+        }
+    }
+}
+
+async fn get_wss_client(chain: &str, url: &str) -> Result<Provider<Ws>, Fatal> {
+    Provider::<Ws>::connect(url)
         .await
-        .unwrap()
+        .map_err(|e| Fatal::Rpc(format!("{} websocket: {}", chain, e)))
 }
 
-async fn get_http_client() -> Provider<Http> {
-    Provider::<Http>::try_from(dotenv::var("HTTP_RPC").unwrap().as_str())
-        .expect("could not instantiate HTTP Provider")
+async fn get_http_client(url: &str) -> Provider<Http> {
+    // bound every RPC call so a hung node can't stall event processing
+    let client = http::builder("RPC_HEADERS")
+        .timeout(Duration::from_secs(config::var_or("HTTP_TIMEOUT_SECS", 30)))
+        .build()
+        .expect("could not build HTTP client");
+
+    let url = url
+        .parse::<reqwest::Url>()
+        .expect("could not parse HTTP_RPC");
+
+    Provider::new(Http::new_with_client(url, client))
 }
 
-async fn send_to_telegram(log: FragmentNftFilter, meta: LogMeta) {
-    let msg = message::Message::default().fill_message(log, meta).await;
-    println!("Total Profit: {}", msg.total_profit);
+/// How long ago the block containing `meta` was mined.
+async fn event_age(state: &AppState, meta: &LogMeta) -> eyre::Result<Duration> {
+    let block = state
+        .flooring
+        .client()
+        .get_block(meta.block_number)
+        .await?
+        .ok_or_else(|| eyre::eyre!("block {} not found", meta.block_number))?;
+
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    Ok(now.saturating_sub(Duration::from_secs(block.timestamp.as_u64())))
+}
 
-    if msg.total_profit <= dotenv::var("MINIMUM_PROFIT").unwrap().parse::<f64>().unwrap() {
-        println!("Profit too low, not sending message");
+async fn send_to_telegram(log: FragmentNftFilter, meta: LogMeta, state: &AppState) {
+    let collection = format!("{:#x}", log.collection);
+
+    if state.mute_list.is_muted(&collection) {
+        info!("Collection {} is muted, not processing event", collection);
         return;
     }
 
-    // create Bot
-    let bot = Bot::new(dotenv::var("TELEGRAM_BOT_TOKEN").unwrap());
-    // set parsemode to html
-    let bot = bot.parse_mode(teloxide::types::ParseMode::Html);
-    match bot
-        .send_message(
-            "@flooring_monitor".to_string(),
-            msg.to_string(),
-        )
-        .send()
-        .await
+    // also covers allowlists too long to filter on the node
+    if !state.chain.allows(&log.collection) {
+        info!(
+            "Collection {} is not allowlisted, not processing event",
+            collection
+        );
+        return;
+    }
+
+    // the same event delivered twice, e.g. replayed by the node after a reconnect
+    if !state.dedup.first_seen(&meta).await {
+        info!("Event already seen, not processing it");
+        return;
+    }
+
+    // tokens alerted on recently, in any transaction, would only repeat that alert
+    let mut log = log;
+    let mut fresh = Vec::new();
+    for token_id in std::mem::take(&mut log.token_ids) {
+        if !state.dedup.recently_alerted(&collection, token_id).await {
+            fresh.push(token_id);
+        }
+    }
+    log.token_ids = fresh;
+    if log.token_ids.is_empty() {
+        info!("Every token in the event was alerted on recently, not processing it");
+        return;
+    }
+
+    // skip small fragmentations before spending any API calls on them
+    let min_tokens: usize = config::var_or("MIN_TOKENS_PER_EVENT", 0);
+    if log.token_ids.len() < min_tokens {
+        info!(
+            "Event has {} tokens, fewer than {}, not processing it",
+            log.token_ids.len(),
+            min_tokens
+        );
+        return;
+    }
+
+    // an event from minutes ago is no longer actionable, e.g. one replayed after a reconnect
+    let max_age_secs: u64 = config::var_or("MAX_EVENT_AGE_SECS", 0);
+    if max_age_secs > 0 && !config::var_or("BACKFILL", false) {
+        match event_age(state, &meta).await {
+            Ok(age) if age > Duration::from_secs(max_age_secs) => {
+                let stale_events_dropped = &state.metrics.stale_events_dropped;
+                let dropped = stale_events_dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                info!(
+                    "Dropping event from {}s ago, older than {}s ({} stale events dropped)",
+                    age.as_secs(),
+                    max_age_secs,
+                    dropped
+                );
+                return;
+            }
+            Ok(_) => {}
+            // process it anyway rather than lose a possibly fresh event
+            Err(e) => error!("Error getting event block timestamp: {:?}", e),
+        }
+    }
+
+    let mut msg = message::Message::default()
+        .fill_message(log.clone(), meta.clone(), state)
+        .await;
+    state.metrics.events_processed.fetch_add(1, Ordering::Relaxed);
+    state.metrics.record_errors(&msg.errors);
+
+    // a momentary API blip can leave many fields empty, so rebuild the whole message a few
+    // times before settling for a degraded alert
+    let retry_missing_fields: usize = config::var_or("FILL_RETRY_MISSING_FIELDS", 0);
+    let max_retries: u32 = config::var_or("FILL_RETRY_MAX", 2);
+    let mut retries = 0;
+    while retry_missing_fields > 0
+        && msg.errors.len() >= retry_missing_fields
+        && retries < max_retries
     {
-        Ok(_) => println!("Message sent"),
-        Err(e) => {
-            println!("Error sending message: {:?}", e);
-            // sleep for 35 seconds to avoid spamming telegram
-            tokio::time::sleep(tokio::time::Duration::from_secs(35)).await;
+        retries += 1;
+        warn!(
+            "{} fields failed, rebuilding message (retry {} of {})",
+            msg.errors.len(),
+            retries,
+            max_retries
+        );
+        tokio::time::sleep(Duration::from_millis(config::var_or(
+            "FILL_RETRY_DELAY_MS",
+            2_000,
+        )))
+        .await;
+
+        let retried = message::Message::default()
+            .fill_message(log.clone(), meta.clone(), state)
+            .await;
+        state.metrics.record_errors(&retried.errors);
+
+        // keep the best attempt, so a worse retry never replaces a better message
+        if retried.errors.len() <= msg.errors.len() {
+            msg = retried;
+        }
+    }
+    info!("Total Profit: {}", msg.total_profit);
+    state.metrics.observe_profit(msg.total_profit);
+
+    // every fragment may start a cycle, whether or not it is worth an alert
+    if let Some(cycles) = &state.cycles {
+        cycles.record(&msg);
+    }
+
+    // recorded like any other event, but never alerted on whatever the profit
+    if config::collections("OBSERVE_ONLY").contains(&collection) {
+        info!(
+            "Collection {} is observe only, recording without alerting",
+            collection
+        );
+        append_csv(&msg);
+        return;
+    }
+
+    let minimum_profit = dotenv::var("MINIMUM_PROFIT").unwrap().parse::<Decimal>().unwrap();
+    // optionally discount flaky bid sources before deciding whether an alert is worth sending
+    let filter_on_expected = config::var_or("FILTER_ON_EXPECTED_PROFIT", false);
+    let alert_profit = |msg: &message::Message| {
+        if filter_on_expected {
+            msg.expected_total_profit
+        } else {
+            msg.total_profit
+        }
+    };
+
+    if alert_profit(&msg) <= minimum_profit {
+        info!("Profit too low, not sending message");
+        return;
+    }
+
+    // the profit rests on a MuToken price nobody can trade into
+    if msg.illiquid() && matches!(dotenv::var("LOW_LIQUIDITY_POLICY").as_deref(), Ok("skip")) {
+        info!("MuToken liquidity too low, not sending message");
+        return;
+    }
+
+    // big numbers get a second look so a stale bid doesn't produce a big false alert
+    if config::var_or("CONFIRM_HIGH_VALUE", false)
+        && config::var_opt::<Decimal>("HIGH_VALUE_THRESHOLD")
+            .is_some_and(|threshold| msg.total_profit >= threshold)
+    {
+        info!("High value alert, re-checking prices");
+        let recorded = msg.errors.len();
+        msg = msg.reconfirm(state).await;
+        state.metrics.record_errors(&msg.errors[recorded..]);
+        info!("Confirmed Total Profit: {}", msg.total_profit);
+
+        if alert_profit(&msg) <= minimum_profit {
+            info!("Profit too low on re-check, not sending message");
+            return;
+        }
+    }
+
+    // profit this high means bad upstream data, e.g. a MuToken price parsed as near zero
+    let max_plausible_profit = config::var_or("MAX_PLAUSIBLE_PROFIT", Decimal::from(1000));
+    if msg.total_profit > max_plausible_profit {
+        error!(
+            "Error: implausible profit of {} ETH for {} (max {}), not sending message",
+            msg.total_profit, collection, max_plausible_profit
+        );
+        return;
+    }
+
+    // a better bid on a recently alerted token updates that alert, so it isn't held back like a
+    // new one. Telegram edits its message, other notifiers send a new one.
+    if state.sent_alerts.improved_on(&msg).is_some() {
+        info!("Improves on a recent alert for the same token, updating it");
+        deliver(state, vec![msg]).await;
+        return;
+    }
+
+    if !state.cooldown.try_acquire(&collection) {
+        info!(
+            "Collection {} is cooling down, not sending message",
+            collection
+        );
+        return;
+    }
+
+    // hold non-urgent alerts until quiet hours end
+    let msg = match &state.quiet_hours {
+        Some(quiet_hours) => match quiet_hours.hold(msg) {
+            Some(msg) => msg,
+            None => {
+                info!("Quiet hours, holding message");
+                return;
+            }
+        },
+        None => msg,
+    };
+
+    // coalesce alerts arriving close together, unless this one is worth sending right away
+    if let Some(batch) = &state.batch {
+        if batch
+            .bypass_profit
+            .is_none_or(|bypass_profit| msg.total_profit < bypass_profit)
+        {
+            info!("Adding message to batch");
+            if let Some(msgs) = batch.push(msg) {
+                deliver(state, msgs).await;
+            }
+            return;
+        }
+    }
+
+    deliver(state, vec![msg]).await;
+}
+
+/// Send the due batch every so often.
+async fn flush_batches(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+
+    loop {
+        interval.tick().await;
+
+        if let Some(msgs) = state.batch.as_ref().and_then(|batch| batch.take_due()) {
+            deliver(&state, msgs).await;
+        }
+    }
+}
+
+/// Send the alerts held during quiet hours once they are over.
+async fn flush_quiet_hours(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        if let Some(msgs) = state
+            .quiet_hours
+            .as_ref()
+            .and_then(|quiet_hours| quiet_hours.take_if_over())
+        {
+            release_held(&state, msgs).await;
+        }
+    }
+}
+
+/// Send alerts held during quiet hours, in full or as a summary.
+async fn release_held(state: &AppState, msgs: Vec<message::Message>) {
+    let Some(quiet_hours) = &state.quiet_hours else {
+        return;
+    };
+
+    match quiet_hours.flush {
+        quiet_hours::QuietHoursFlush::All => {
+            for msg in msgs {
+                deliver(state, vec![msg]).await;
+            }
+        }
+        quiet_hours::QuietHoursFlush::Summary => {
+            let text = format!(
+                "{} alerts held during quiet hours:\n{}",
+                msgs.len(),
+                msgs.iter()
+                    .map(|msg| msg.summary_line())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+            publish(state, &msgs);
+            if notifier::notify_all_text(&state.notifiers, &text).await {
+                record_delivered(state, &msgs).await;
+            }
+        }
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM on unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("could not listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Wait up to `SHUTDOWN_TIMEOUT_SECS` for the events still being processed.
+async fn wait_in_flight(in_flight: &tokio::sync::Semaphore, max_in_flight: usize) {
+    let timeout = Duration::from_secs(config::var_or("SHUTDOWN_TIMEOUT_SECS", 10));
+    let all = in_flight.acquire_many(max_in_flight as u32);
+
+    if tokio::time::timeout(timeout, all).await.is_err() {
+        warn!(
+            "Gave up waiting for {} events still being processed",
+            max_in_flight - in_flight.available_permits()
+        );
+    }
+}
+
+/// Send whatever is still batched or held on shutdown, giving up after
+/// `SHUTDOWN_TIMEOUT_SECS`. The ledger is persisted on every alert, so there is nothing else to
+/// flush.
+async fn drain(states: &[Arc<AppState>]) {
+    let pending = states
+        .iter()
+        .map(|state| {
+            let batched = state.batch.as_ref().map(|batch| batch.take_all());
+            let held = state
+                .quiet_hours
+                .as_ref()
+                .map(|quiet_hours| quiet_hours.take_all());
+            (state, batched.unwrap_or_default(), held.unwrap_or_default())
+        })
+        .collect::<Vec<_>>();
+    let total: usize = pending
+        .iter()
+        .map(|(_, batched, held)| batched.len() + held.len())
+        .sum();
+
+    if total == 0 {
+        return;
+    }
+
+    let timeout = Duration::from_secs(config::var_or("SHUTDOWN_TIMEOUT_SECS", 10));
+    info!(
+        "Draining {} pending messages, for up to {}s",
+        total,
+        timeout.as_secs()
+    );
+
+    let mut drained = 0;
+    let finished = tokio::time::timeout(timeout, async {
+        for (state, batched, held) in pending {
+            let count = batched.len();
+            if count > 0 {
+                deliver(state, batched).await;
+                drained += count;
+            }
+
+            let count = held.len();
+            if count > 0 {
+                release_held(state, held).await;
+                drained += count;
+            }
+        }
+    })
+    .await
+    .is_ok();
+
+    if finished {
+        info!("Drained {} messages", drained);
+    } else {
+        warn!(
+            "Shutdown timed out, drained {} messages and dropped {}",
+            drained,
+            total - drained
+        );
+    }
+}
+
+/// Send whatever is still batched or held before exiting.
+async fn flush_pending(state: &AppState) {
+    if let Some(batch) = &state.batch {
+        let msgs = batch.take_all();
+        if !msgs.is_empty() {
+            deliver(state, msgs).await;
+        }
+    }
+
+    if let Some(quiet_hours) = &state.quiet_hours {
+        let msgs = quiet_hours.take_all();
+        if !msgs.is_empty() {
+            release_held(state, msgs).await;
+        }
+    }
+}
+
+/// Send one or more alerts to every configured notifier.
+async fn deliver(state: &AppState, msgs: Vec<message::Message>) {
+    publish(state, &msgs);
+
+    if notifier::notify_all(&state.notifiers, &msgs).await {
+        record_delivered(state, &msgs).await;
+    }
+}
+
+/// Stream `msgs` to the `/events` subscribers.
+fn publish(state: &AppState, msgs: &[message::Message]) {
+    // skip serializing when nobody is listening on /events
+    if state.opportunities.receiver_count() == 0 {
+        return;
+    }
+
+    for msg in msgs {
+        match msg.to_json() {
+            Ok(json) => {
+                let _ = state.opportunities.send(json);
+            }
+            Err(e) => error!("Error serializing alert: {:?}", e),
+        }
+    }
+}
+
+/// Count, dedup, ledger and CSV bookkeeping for alerts at least one notifier delivered, in full
+/// or as part of a summary.
+async fn record_delivered(state: &AppState, msgs: &[message::Message]) {
+    for msg in msgs {
+        state.metrics.alerts_sent.fetch_add(1, Ordering::Relaxed);
+        state.dedup.record_alerted(msg.token_keys()).await;
+        state.ledger.record(msg.total_profit);
+        append_csv(msg);
+    }
+}
+
+/// Append `msg` to `CSV_FILE`, if set.
+fn append_csv(msg: &message::Message) {
+    if let Ok(path) = dotenv::var("CSV_FILE") {
+        if let Err(e) = csv_sink::append(path.as_ref(), msg) {
+            error!("Error appending to CSV: {:?}", e);
         }
     }
 }
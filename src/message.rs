@@ -1,94 +1,1000 @@
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::panic::AssertUnwindSafe;
 
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use ethers::abi::Address;
 use indoc::formatdoc;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use serde::Serialize;
 
 use ethers::types::{H160, U256};
 
 use ethers::prelude::LogMeta;
-
+use ethers::providers::Middleware;
+use futures::{FutureExt, StreamExt};
+use tracing::{debug, error, info, warn};
+
+use crate::blur::{BlurBidMode, BlurConfig};
+use crate::error::{api_key, get_json, ApiProvider, ErrorCategory, MessageError};
+use crate::state::AppState;
+use crate::valuation_endpoint::{ValuationEndpoint, ValuationEndpointMode};
 use crate::FragmentNftFilter;
 
-#[derive(Clone, Debug, Default)]
+/// Raw fragment token amount minted per NFT, used when it can't be read from chain.
+/// Flooring mints 1,000,000 μTokens (18 decimals) for every fragmented NFT.
+const DEFAULT_FRAGMENTS_PER_NFT: u128 = 1_000_000 * 10u128.pow(18);
+
+/// fragmentToken, freeNftLength, lastUpdatedBucket, nextKeyId, activeSafeBoxCnt, infiniteCnt, nextActivityId
+pub(crate) type CollectionInfo = (Address, U256, u64, u64, u64, u64, u64);
+
+#[derive(Clone, Debug, Default, Serialize)]
 pub(crate) struct Message {
+    collection: String,
+    tx_hash: String,
     etherscan_link: String,
+    tx_details: Option<TxDetails>,
+    /// `(event block, blocks between it and the chain head)`, when `INCLUDE_CONFIRMATIONS` is set
+    confirmations: Option<(u64, u64)>,
     collection_header: String,
-    mu_token: MuToken,
-    pub total_profit: f64,
+    collection_stats: Option<CollectionStats>,
+    mu_token: Option<MuToken>,
+    /// the collection has no MuToken at all, as opposed to its details failing to load
+    no_mu_token: bool,
+    /// only worked out when `INCLUDE_PULLED_BID_RISK` is set
+    pulled_bid_risk: Option<PulledBidRisk>,
+    pub total_profit: Decimal,
+    /// `total_profit` as a percentage of what the profitable tokens cost in MuTokens
+    total_profit_pct: Option<f64>,
+    /// sum of the positive expected profits, weighted by each top bid's fill probability
+    pub expected_total_profit: Decimal,
     tokens: Vec<Token>,
+    /// tokens with a known profit below this are left out of the rendered message. They still
+    /// count toward `total_profit` as usual, since hiding is purely presentational.
+    #[serde(skip)]
+    hide_below: Option<Decimal>,
+    /// only this many of the shown tokens, the most profitable, are rendered in full, from
+    /// `MAX_DETAILED_TOKENS`. The rest are summed up in one line.
+    #[serde(skip)]
+    max_detailed: Option<usize>,
+    sell_price_basis: SellPriceBasis,
+    fees: Fees,
+    /// whether to show each token's breakeven MuToken price, from `SHOW_BREAKEVEN`
+    #[serde(skip)]
+    show_breakeven: bool,
+    /// (provider, category) of every upstream error hit while building the message
+    #[serde(skip)]
+    pub(crate) errors: Vec<(ApiProvider, ErrorCategory)>,
+    /// the decoded event exactly as received, only kept when `INCLUDE_RAW_LOG` is set
+    raw_log: Option<String>,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub(crate) struct Token {
+    /// decimal everywhere, like every marketplace and API we link to
+    #[serde(serialize_with = "serialize_decimal")]
     token_id: U256,
-    blur_link: String,
-    flooring_link: String,
-    opensea_pro_link: String,
+    /// (marketplace name, url) for every configured marketplace
+    links: Vec<(String, String)>,
     valuation: Option<Valuation>,
-    top_bid: TopBid,
-    profit: Option<f64>,
+    top_bid: Option<TopBid>,
+    profit: Option<Decimal>,
+    /// profit at the live market derived price, only set when a cost basis override is in effect
+    market_profit: Option<Decimal>,
+    /// `profit` as a percentage of the MuToken cost, unset when the cost is zero or unknown
+    profit_pct: Option<f64>,
+    /// `profit` discounted by the chance the top bid's source actually fills
+    expected_profit: Option<Decimal>,
+    /// the fees taken out of `profit`, when any are configured
+    fee_breakdown: Option<FeeBreakdown>,
+    /// the MuToken derived price at which `profit` would be zero, known whenever the sell price is
+    breakeven: Option<Decimal>,
+    /// true when no source returned usable price data for this token
+    no_data: bool,
+    /// token image from Reservoir metadata, only fetched when Discord output is enabled, or from
+    /// `tokenURI` when `RESOLVE_TOKEN_URI` is set
+    image: Option<String>,
+    /// token name from `tokenURI`, when `RESOLVE_TOKEN_URI` is set
+    name: Option<String>,
+    /// the token's most recent sale, only fetched when `INCLUDE_LAST_SALE` is set
+    last_sale: Option<LastSale>,
+    /// where the token sits in the Flooring vault, only checked when `VAULT_STATUS_CHECK` is set
+    vault_status: Option<VaultStatus>,
+    /// top bid / valuation, when it falls outside the configured bounds
+    bid_ratio_flag: Option<BidRatioFlag>,
+}
+
+/// Version of the JSON alerts are published as, see `Message::to_json`. Bump it on a breaking
+/// change, i.e. a field removed, renamed or changing type. New fields don't need a bump.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+pub(crate) const CSV_HEADER: &str =
+    "timestamp,tx_hash,collection,token_id,derived_price,top_bid,valuation,profit";
+
+impl Token {
+    /// Profit needs both legs of the trade, so it is only known with a sell price and a MuToken
+    /// price.
+    fn compute_profit(&mut self, mu_token: Option<&MuToken>, basis: SellPriceBasis, fees: &Fees) {
+        self.profit = None;
+        self.market_profit = None;
+        self.profit_pct = None;
+        self.expected_profit = None;
+        self.fee_breakdown = None;
+        self.breakeven = self
+            .sell_price(basis)
+            .map(|sell_price| fees.breakeven_cost(sell_price));
+
+        if let (Some(sell_price), Some(mu_token)) = (self.sell_price(basis), mu_token) {
+            let fee_breakdown = fees.breakdown(sell_price, mu_token.cost());
+            let profit = sell_price - mu_token.cost() - fee_breakdown.total();
+
+            self.profit = Some(profit);
+            self.profit_pct = percent_of(profit, mu_token.cost());
+            self.expected_profit = Some(
+                profit * Decimal::from_f64(self.fill_probability()).unwrap_or(Decimal::ONE),
+            );
+            if !fees.is_zero() {
+                self.fee_breakdown = Some(fee_breakdown);
+            }
+
+            if mu_token.cost_basis.is_some() {
+                let market_fees = fees.breakdown(sell_price, mu_token.derived_price);
+                self.market_profit =
+                    Some(sell_price - mu_token.derived_price - market_fees.total());
+            }
+        }
+    }
+
+    /// Mark the token when no price source returned anything for it, returning false when
+    /// `policy` drops such tokens.
+    fn apply_no_data_policy(&mut self, mu_token_known: bool, policy: NoDataPolicy) -> bool {
+        self.no_data = self.valuation.is_none() && self.top_bid.is_none() && !mu_token_known;
+
+        !(self.no_data && policy == NoDataPolicy::Skip)
+    }
+
+    /// How likely the top bid is to fill, 1.0 when there is no factor for its source.
+    fn fill_probability(&self) -> f64 {
+        self.top_bid
+            .as_ref()
+            .and_then(|top_bid| top_bid.fill_probability)
+            .unwrap_or(1f64)
+    }
+
+    /// The price the NFT is assumed to sell for. `min` and `max` fall back to whichever of the
+    /// top bid and valuation is available when only one is.
+    fn sell_price(&self, basis: SellPriceBasis) -> Option<Decimal> {
+        let top_bid = self.top_bid.as_ref().map(|top_bid| top_bid.price);
+        let valuation = self.valuation.as_ref().map(|valuation| valuation.price);
+
+        match basis {
+            SellPriceBasis::TopBid => top_bid,
+            SellPriceBasis::Valuation => valuation,
+            SellPriceBasis::Min => match (top_bid, valuation) {
+                (Some(top_bid), Some(valuation)) => Some(top_bid.min(valuation)),
+                (top_bid, valuation) => top_bid.or(valuation),
+            },
+            SellPriceBasis::Max => match (top_bid, valuation) {
+                (Some(top_bid), Some(valuation)) => Some(top_bid.max(valuation)),
+                (top_bid, valuation) => top_bid.or(valuation),
+            },
+        }
+    }
+}
+
+/// Fee assumptions taken out of profit on top of what the prices already include. Reservoir
+/// bids are `netAmount`, already net of royalties and the bid marketplace's fee, so
+/// `MARKETPLACE_FEE_PCT` is only for costs beyond those, or for valuation based profit.
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct Fees {
+    /// percent of the MuToken cost lost buying it, e.g. DEX or aggregator fees (`TAKER_FEE_PCT`)
+    taker_pct: Decimal,
+    /// percent of the sell price lost selling, from `MARKETPLACE_FEE_PCT` or its per-collection
+    /// override in `COLLECTION_MARKETPLACE_FEE_PCT`
+    marketplace_pct: Decimal,
+    /// flat gas cost per token, in ETH (`GAS_COST_ETH`)
+    gas: Decimal,
+}
+
+/// What each fee comes to for one token, in ETH.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub(crate) struct FeeBreakdown {
+    taker: Decimal,
+    marketplace: Decimal,
+    gas: Decimal,
+}
+
+impl Fees {
+    fn from_env(collection: &str) -> Self {
+        Fees {
+            taker_pct: crate::config::var_or("TAKER_FEE_PCT", Decimal::ZERO),
+            marketplace_pct: crate::config::per_collection::<Decimal>(
+                "COLLECTION_MARKETPLACE_FEE_PCT",
+            )
+            .remove(collection)
+            .unwrap_or_else(|| crate::config::var_or("MARKETPLACE_FEE_PCT", Decimal::ZERO)),
+            gas: crate::config::var_or("GAS_COST_ETH", Decimal::ZERO),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.taker_pct.is_zero() && self.marketplace_pct.is_zero() && self.gas.is_zero()
+    }
+
+    /// The MuToken cost at which selling at `sell_price` exactly breaks even after fees, solving
+    /// `sell_price - cost - breakdown(sell_price, cost).total() = 0` for `cost`.
+    fn breakeven_cost(&self, sell_price: Decimal) -> Decimal {
+        let proceeds = sell_price * (Decimal::ONE - self.marketplace_pct / Decimal::ONE_HUNDRED);
+        (proceeds - self.gas) / (Decimal::ONE + self.taker_pct / Decimal::ONE_HUNDRED)
+    }
+
+    fn breakdown(&self, sell_price: Decimal, cost: Decimal) -> FeeBreakdown {
+        FeeBreakdown {
+            taker: cost * self.taker_pct / Decimal::ONE_HUNDRED,
+            marketplace: sell_price * self.marketplace_pct / Decimal::ONE_HUNDRED,
+            gas: self.gas,
+        }
+    }
+}
+
+impl FeeBreakdown {
+    fn total(&self) -> Decimal {
+        self.taker + self.marketplace + self.gas
+    }
+}
+
+impl Display for FeeBreakdown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Fees: {} ETH taker + {} ETH marketplace + {} ETH gas",
+            self.taker.normalize(),
+            self.marketplace.normalize(),
+            self.gas.normalize()
+        )
+    }
+}
+
+/// Which figure profit treats as the sell price, from `SELL_PRICE_BASIS`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SellPriceBasis {
+    #[default]
+    TopBid,
+    Valuation,
+    Min,
+    Max,
+}
+
+impl SellPriceBasis {
+    fn from_env() -> Self {
+        match dotenv::var("SELL_PRICE_BASIS").as_deref() {
+            Ok("valuation") => SellPriceBasis::Valuation,
+            Ok("min") => SellPriceBasis::Min,
+            Ok("max") => SellPriceBasis::Max,
+            Ok("top_bid") | Err(_) => SellPriceBasis::TopBid,
+            Ok(other) => {
+                warn!("Unknown SELL_PRICE_BASIS {}, using top_bid", other);
+                SellPriceBasis::TopBid
+            }
+        }
+    }
+}
+
+impl Display for SellPriceBasis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let basis = match self {
+            SellPriceBasis::TopBid => "top bid",
+            SellPriceBasis::Valuation => "valuation",
+            SellPriceBasis::Min => "min of top bid and valuation",
+            SellPriceBasis::Max => "max of top bid and valuation",
+        };
+
+        write!(f, "{}", basis)
+    }
+}
+
+/// The order tokens are listed in, from `TOKEN_SORT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TokenSort {
+    /// the order Flooring emitted them in
+    Emitted,
+    ProfitDesc,
+    TokenId,
+    ValuationDesc,
+}
+
+impl TokenSort {
+    fn from_env() -> Self {
+        match dotenv::var("TOKEN_SORT").as_deref() {
+            Ok("profit_desc") => TokenSort::ProfitDesc,
+            Ok("token_id") => TokenSort::TokenId,
+            Ok("valuation_desc") => TokenSort::ValuationDesc,
+            Ok("emitted") | Err(_) => TokenSort::Emitted,
+            Ok(other) => {
+                warn!("Unknown TOKEN_SORT {}, using emitted", other);
+                TokenSort::Emitted
+            }
+        }
+    }
+
+    /// Sort `tokens` in place. Tokens without the figure sorted on go last, and the sort is
+    /// stable so ties keep their emitted order.
+    fn apply(self, tokens: &mut [Token]) {
+        match self {
+            TokenSort::Emitted => {}
+            TokenSort::ProfitDesc => {
+                tokens.sort_by_key(|token| std::cmp::Reverse(token.profit));
+            }
+            TokenSort::TokenId => tokens.sort_by_key(|token| token.token_id),
+            TokenSort::ValuationDesc => tokens.sort_by_key(|token| {
+                std::cmp::Reverse(token.valuation.as_ref().map(|valuation| valuation.price))
+            }),
+        }
+    }
+}
+
+/// Marketplace link templates used when `MARKETPLACE_LINKS` is unset.
+const DEFAULT_MARKETPLACE_LINKS: &str = "Blur=https://blur.io/asset/{collection}/{token_id};Flooring=https://www.flooring.io/nft-details/{collection}/{token_id};OpenSea Pro=https://pro.opensea.io/nft/{collection}/{token_id}";
+
+/// Where to execute the other leg of the trade on Flooring when `FLOORING_ACTION_LINK` is unset.
+const DEFAULT_FLOORING_ACTION_LINK: &str = "https://www.flooring.io/collection/{collection}";
+
+/// A per-token marketplace link, with `{collection}` and `{token_id}` placeholders in the url.
+#[derive(Clone, Debug)]
+pub(crate) struct MarketplaceLink {
+    name: String,
+    template: String,
+}
+
+impl MarketplaceLink {
+    /// Parse `MARKETPLACE_LINKS`, a `;` separated list of `Name=url template` entries.
+    /// Set it to an empty string to show no links at all.
+    fn from_env() -> Vec<Self> {
+        let links = dotenv::var("MARKETPLACE_LINKS")
+            .unwrap_or_else(|_| DEFAULT_MARKETPLACE_LINKS.to_string());
+
+        Self::parse(&links)
+    }
+
+    fn parse(links: &str) -> Vec<Self> {
+        links
+            .split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .filter_map(|entry| match entry.split_once('=') {
+                Some((name, template)) => Some(MarketplaceLink {
+                    name: name.trim().to_string(),
+                    template: template.trim().to_string(),
+                }),
+                None => {
+                    warn!("Ignoring malformed marketplace link: {}", entry);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The Flooring page for redeeming or claiming from the collection's vault, from the
+    /// `FLOORING_ACTION_LINK` template. Set it to an empty string to leave the link out.
+    fn flooring_action() -> Option<Self> {
+        let template = dotenv::var("FLOORING_ACTION_LINK")
+            .unwrap_or_else(|_| DEFAULT_FLOORING_ACTION_LINK.to_string());
+
+        (!template.trim().is_empty()).then(|| MarketplaceLink {
+            name: "Redeem on Flooring".to_string(),
+            template: template.trim().to_string(),
+        })
+    }
+
+    fn url(&self, collection: &str, token_id: U256) -> String {
+        self.template
+            .replace("{collection}", collection)
+            .replace("{token_id}", &token_id.to_string())
+    }
+}
+
+/// What to do with a token for which every price source failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum NoDataPolicy {
+    /// drop the token from the message, logging why
+    Skip,
+    /// keep the token but mark its profit as untrustworthy
+    Flag,
+}
+
+impl NoDataPolicy {
+    fn from_env() -> Self {
+        match dotenv::var("NO_DATA_POLICY").as_deref() {
+            Ok("skip") => NoDataPolicy::Skip,
+            _ => NoDataPolicy::Flag,
+        }
+    }
+}
+
+/// Per-event settings for building each token, read once from env.
+struct TokenOptions {
+    no_data_policy: NoDataPolicy,
+    marketplace_links: Vec<MarketplaceLink>,
+    fetch_images: bool,
+    fetch_last_sale: bool,
+    resolve_token_uri: bool,
+    vault_check: VaultCheck,
+    floor_bid_fallback: FloorBidFallback,
+    /// the collection's top bid, only fetched when `floor_bid_fallback` is on
+    floor_bid: Option<TopBid>,
+}
+
+/// Whether and how to check each token's vault status, from `VAULT_STATUS_CHECK`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum VaultCheck {
+    Off,
+    /// show the status next to each token
+    Annotate,
+    /// also drop tokens that are no longer free in the vault
+    Filter,
+}
+
+impl VaultCheck {
+    fn from_env() -> Self {
+        match dotenv::var("VAULT_STATUS_CHECK").as_deref() {
+            Ok("annotate") => VaultCheck::Annotate,
+            Ok("filter") => VaultCheck::Filter,
+            _ => VaultCheck::Off,
+        }
+    }
+}
+
+/// Where a token is relative to the Flooring vault, read from chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum VaultStatus {
+    /// held by the vault with no safebox, so it can be redeemed with MuTokens
+    Free,
+    /// held by the vault but locked in a safebox, from `getSafeBox().expiryTs` in unix seconds,
+    /// where zero is a safebox that never expires
+    Locked { expiry: u32 },
+    /// no longer held by the vault, e.g. already redeemed
+    NotInVault,
+    /// the chain could not tell us, e.g. `ownerOf` reverted for a burned token
+    Unknown,
+}
+
+impl Display for VaultStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VaultStatus::Free => write!(f, "free in vault, redeemable now"),
+            VaultStatus::Locked { expiry: 0 } => {
+                write!(f, "locked in a safebox with no expiry, not redeemable")
+            }
+            VaultStatus::Locked { expiry } => match Utc.timestamp_opt(*expiry as i64, 0).single() {
+                // an expired safebox goes to auction rather than back to the free pool
+                Some(expiry) if expiry <= Utc::now() => write!(
+                    f,
+                    "safebox expired {}, not redeemable until it is auctioned",
+                    expiry.format("%Y-%m-%d %H:%M UTC")
+                ),
+                Some(expiry) => write!(
+                    f,
+                    "locked in a safebox, not redeemable before {}",
+                    expiry.format("%Y-%m-%d %H:%M UTC")
+                ),
+                None => write!(f, "locked in a safebox"),
+            },
+            VaultStatus::NotInVault => write!(f, "no longer in vault"),
+            VaultStatus::Unknown => write!(f, "vault status unknown"),
+        }
+    }
+}
+
+/// Sanity bounds on top bid / valuation, from `MAX_BID_VALUATION_RATIO` and
+/// `MIN_BID_VALUATION_RATIO`. A bid far above valuation may be a fat finger or manipulated.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BidRatioBounds {
+    max: Option<f64>,
+    min: Option<f64>,
+    /// drop tokens whose bid is suspiciously high instead of only flagging them
+    suppress: bool,
+}
+
+/// Which way a token's bid / valuation ratio broke the bounds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BidRatioFlag {
+    AboveValuation(f64),
+    BelowValuation(f64),
+}
+
+impl BidRatioBounds {
+    fn from_env() -> Self {
+        BidRatioBounds {
+            max: dotenv::var("MAX_BID_VALUATION_RATIO")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            min: dotenv::var("MIN_BID_VALUATION_RATIO")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            suppress: matches!(
+                dotenv::var("SUSPICIOUS_BID_POLICY").as_deref(),
+                Ok("suppress")
+            ),
+        }
+    }
+
+    /// Needs both a top bid and a positive valuation, otherwise there is nothing to compare.
+    fn check(&self, token: &Token) -> Option<BidRatioFlag> {
+        let top_bid = token.top_bid.as_ref()?.price;
+        let valuation = token.valuation.as_ref()?.price;
+        if valuation <= Decimal::ZERO {
+            return None;
+        }
+        let ratio = (top_bid / valuation).to_f64()?;
+
+        match (self.max, self.min) {
+            (Some(max), _) if ratio > max => Some(BidRatioFlag::AboveValuation(ratio)),
+            (_, Some(min)) if ratio < min => Some(BidRatioFlag::BelowValuation(ratio)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for BidRatioFlag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BidRatioFlag::AboveValuation(ratio) => write!(
+                f,
+                "⚠️ Suspicious bid: {:.1}x valuation, possibly a fat finger or manipulated",
+                ratio
+            ),
+            BidRatioFlag::BelowValuation(ratio) => {
+                write!(f, "⚠️ Bid is only {:.2}x valuation", ratio)
+            }
+        }
+    }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub(crate) struct Valuation {
+    /// "DeepNFTValue", "Reservoir floor", or `VALUATION_ENDPOINT_NAME` for a custom endpoint
+    source: String,
+    /// empty when a custom endpoint gives no link
     url: String,
-    price: f64,
+    price: Decimal,
+    /// when the source last appraised the token, if it says
+    updated_at: Option<DateTime<Utc>>,
+    /// older than `VALUATION_MAX_AGE_HOURS`
+    stale: bool,
+}
+
+/// Where a collection's valuations come from, per collection from `VALUATION_SOURCES` (e.g.
+/// `0xabc=floor;0xdef=endpoint`) and otherwise from `VALUATION_SOURCE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ValuationSource {
+    DeepNftValue,
+    /// the `VALUATION_ENDPOINT` service
+    Endpoint,
+    /// the collection's Reservoir floor ask
+    Floor,
+}
+
+impl FromStr for ValuationSource {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deepnftvalue" => Ok(ValuationSource::DeepNftValue),
+            "endpoint" => Ok(ValuationSource::Endpoint),
+            "floor" => Ok(ValuationSource::Floor),
+            other => eyre::bail!("unknown valuation source {}", other),
+        }
+    }
+}
+
+impl ValuationSource {
+    /// Without `VALUATION_SOURCE` the default is the endpoint in override mode, and DeepNFTValue
+    /// otherwise.
+    fn for_collection(collection: &str, endpoint: Option<&ValuationEndpoint>) -> Self {
+        let source = crate::config::per_collection::<ValuationSource>("VALUATION_SOURCES")
+            .remove(&collection.to_lowercase())
+            .or_else(|| {
+                let source = dotenv::var("VALUATION_SOURCE").ok()?;
+                source
+                    .parse()
+                    .map_err(|e| warn!("Ignoring VALUATION_SOURCE: {}", e))
+                    .ok()
+            })
+            .unwrap_or(match endpoint {
+                Some(endpoint) if endpoint.mode == ValuationEndpointMode::Override => {
+                    ValuationSource::Endpoint
+                }
+                _ => ValuationSource::DeepNftValue,
+            });
+
+        if source == ValuationSource::Endpoint && endpoint.is_none() {
+            warn!(
+                "Valuation source for {} is endpoint but VALUATION_ENDPOINT is not set, using DeepNFTValue",
+                collection
+            );
+            return ValuationSource::DeepNftValue;
+        }
+
+        source
+    }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub(crate) struct TopBid {
     url: String,
+    /// the bid's source marketplace, as quoted JSON
     kind: String,
-    price: f64,
+    /// what the bid is on, from Reservoir's `criteria.kind`
+    scope: Option<BidScope>,
+    /// how many NFTs the bid still wants, from `quantityRemaining`
+    quantity: Option<u64>,
+    price: Decimal,
+    /// Blur's own bid, set when it was checked directly against a Blur-sourced Reservoir bid
+    blur_price: Option<Decimal>,
+    /// true when `blur_price` disagrees with Reservoir by more than `BLUR_DISCREPANCY_PCT`
+    blur_discrepancy: bool,
+    /// higher bids skipped because their source is not in `BID_SOURCE_ALLOWLIST`
+    excluded: usize,
+    /// chance a bid from this source fills, from `FILL_PROBABILITY`
+    fill_probability: Option<f64>,
+    /// (amount, symbol) in the currency the bid was made in, when that isn't ETH and
+    /// `SHOW_BID_CURRENCY` is on
+    native: Option<(Decimal, String)>,
+    /// an ETH or WETH bid whose ETH price differs from its own amount by more than
+    /// `BID_CURRENCY_MISMATCH_PCT`
+    currency_mismatch: bool,
+    /// the collection's top bid, standing in for the token's per `FLOOR_BID_FALLBACK`
+    floor_bid: bool,
+}
+
+/// When the collection's top bid is used as a token's sell price instead of the token's own, from
+/// `FLOOR_BID_FALLBACK`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FloorBidFallback {
+    Off,
+    /// only for tokens without a usable bid of their own
+    Missing,
+    /// also when it is higher than the token's own bid
+    Better,
+}
+
+impl FloorBidFallback {
+    fn from_env() -> Self {
+        match dotenv::var("FLOOR_BID_FALLBACK").as_deref() {
+            Ok("missing") => FloorBidFallback::Missing,
+            Ok("better") => FloorBidFallback::Better,
+            Ok("off") | Err(_) => FloorBidFallback::Off,
+            Ok(other) => {
+                warn!("Unknown FLOOR_BID_FALLBACK {}, using off", other);
+                FloorBidFallback::Off
+            }
+        }
+    }
+
+    fn choose(self, top_bid: Option<TopBid>, floor_bid: Option<&TopBid>) -> Option<TopBid> {
+        match (self, top_bid, floor_bid) {
+            (FloorBidFallback::Off, top_bid, _) => top_bid,
+            (_, None, floor_bid) => floor_bid.cloned(),
+            (FloorBidFallback::Better, Some(top_bid), Some(floor_bid))
+                if floor_bid.price > top_bid.price =>
+            {
+                Some(floor_bid.clone())
+            }
+            (_, top_bid, _) => top_bid,
+        }
+    }
+}
+
+/// What a bid can be filled with. Only a token bid is certain to take this exact token, a
+/// collection or attribute bid may be filled by someone else's NFT first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BidScope {
+    Token,
+    Collection,
+    Attribute,
+    /// anything else Reservoir reports, e.g. a custom token set
+    Other,
+}
+
+impl BidScope {
+    fn from_json(kind: &serde_json::Value) -> Option<Self> {
+        Some(match kind.as_str()? {
+            "token" => BidScope::Token,
+            "collection" => BidScope::Collection,
+            "attribute" => BidScope::Attribute,
+            _ => BidScope::Other,
+        })
+    }
+}
+
+impl Display for BidScope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BidScope::Token => "token bid",
+            BidScope::Collection => "collection bid",
+            BidScope::Attribute => "trait bid",
+            BidScope::Other => "token set bid",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A token's most recent sale, from Reservoir.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LastSale {
+    Never,
+    Sold {
+        /// in ETH, converted by Reservoir when the sale was in another currency
+        price: Decimal,
+        timestamp: Option<DateTime<Utc>>,
+    },
+}
+
+/// What unwinding one NFT would cost if the top bid is pulled before it fills.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct PulledBidRisk {
+    /// "floor" when listing at the collection's floor ask, "MuToken derived price" when the
+    /// floor is unknown
+    basis: &'static str,
+    unwind_price: Decimal,
+    /// MuToken cost and fees minus `unwind_price`, negative when the unwind still profits
+    loss: Decimal,
+}
+
+impl Display for PulledBidRisk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.loss > Decimal::ZERO {
+            write!(
+                f,
+                "⚠️ If the top bid is pulled, unwinding at the {} of {} ETH loses {} ETH per NFT",
+                self.basis,
+                self.unwind_price,
+                self.loss.round_dp(4)
+            )
+        } else {
+            write!(
+                f,
+                "If the top bid is pulled, unwinding at the {} of {} ETH still makes {} ETH per NFT",
+                self.basis,
+                self.unwind_price,
+                (-self.loss).round_dp(4)
+            )
+        }
+    }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct CollectionStats {
+    volume_1day: Option<f64>,
+    listings: Option<u64>,
+    floor: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct TxDetails {
+    gas_used: u64,
+    method_id: String,
+    to: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
 pub(crate) struct MuToken {
     dexscreener_link: String,
     name: String,
-    derived_price: f64,
+    derived_price: Decimal,
+    /// the operator's own MuToken cost per NFT, overriding `derived_price` for profit
+    cost_basis: Option<Decimal>,
+    /// USD liquidity across the MuToken's DEX pools on this chain, only fetched when
+    /// `INCLUDE_LIQUIDITY` or `MIN_MU_TOKEN_LIQUIDITY_USD` is set
+    liquidity_usd: Option<Decimal>,
+    /// liquidity is below `MIN_MU_TOKEN_LIQUIDITY_USD`, so the derived price can't be traded in
+    /// size
+    illiquid: bool,
+    /// why the token `collectionInfo` reported may not be the collection's MuToken
+    identity_warning: Option<String>,
+}
+
+impl MuToken {
+    /// What acquiring one NFT worth of MuTokens costs for profit purposes.
+    pub(crate) fn cost(&self) -> Decimal {
+        self.cost_basis.unwrap_or(self.derived_price)
+    }
+
+    pub(crate) fn derived_price(&self) -> Decimal {
+        self.derived_price
+    }
+
+    pub(crate) fn dexscreener_link(&self) -> &str {
+        &self.dexscreener_link
+    }
+
+    pub(crate) fn illiquid(&self) -> bool {
+        self.illiquid
+    }
 }
 
 impl Display for Message {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         // create the message html that includes the information about the collection and the tokens
         let mut message = formatdoc!(
-            r#"<a href="{0}"> TX </a> with {1} ETH profit
-            {2}
+            r#"{0} with {1} ETH{2} profit
             {3}
+            {4}
 
             "#,
-            self.etherscan_link,
+            // quotes have no transaction to link
+            if self.etherscan_link.is_empty() {
+                "Quote".to_string()
+            } else {
+                format!(r#"<a href="{}"> TX </a>"#, self.etherscan_link)
+            },
             self.total_profit,
+            format_pct(self.total_profit_pct),
             self.collection_header,
-            self.mu_token
+            match &self.mu_token {
+                Some(mu_token) => mu_token.to_string(),
+                None if self.no_mu_token => {
+                    "No MuToken, the collection is not fragmentable on Flooring".to_string()
+                }
+                None => "Error getting MuToken derived price".to_string(),
+            }
         );
 
-        for token in &self.tokens {
+        if let Some(collection_stats) = &self.collection_stats {
+            message.push_str(&format!("{}\n\n", collection_stats));
+        }
+
+        if let Some(tx_details) = &self.tx_details {
+            message.push_str(&format!("{}\n\n", tx_details));
+        }
+
+        if let Some(pulled_bid_risk) = &self.pulled_bid_risk {
+            message.push_str(&format!("{}\n\n", pulled_bid_risk));
+        }
+
+        if let Some((block, confirmations)) = self.confirmations {
+            message.push_str(&format!(
+                "Block {}, {} blocks behind head\n\n",
+                block, confirmations
+            ));
+        }
+
+        let mut hidden = 0;
+        let detailed = self.detailed_tokens();
+        let mut collapsed = 0;
+        let mut collapsed_profit = Decimal::ZERO;
+
+        for (token, detailed) in self.tokens.iter().zip(detailed) {
+            if self.is_hidden(token) {
+                hidden += 1;
+                continue;
+            }
+
+            if !detailed {
+                collapsed += 1;
+                collapsed_profit += token
+                    .profit
+                    .filter(|profit| *profit > Decimal::ZERO)
+                    .unwrap_or_default();
+                continue;
+            }
+
             let valuation = match &token.valuation {
                 Some(valuation) => valuation.to_string(),
-                None => "Error getting DeepNFTValue valuation for token".to_string(),
+                None => "Error getting valuation for token".to_string(),
+            };
+
+            let mut top_bid = match &token.top_bid {
+                Some(top_bid) => top_bid.to_string(),
+                None => "Error getting top bid for token".to_string(),
+            };
+
+            if let Some(last_sale) = &token.last_sale {
+                top_bid.push_str(&format!("\n{}", last_sale));
+            }
+
+            let mut profit = match token.profit {
+                Some(profit) => format!("{} ETH{}", profit, format_pct(token.profit_pct)),
+                None => "unavailable".to_string(),
             };
 
+            if let (Some(expected_profit), Some(fill_probability)) = (
+                token.expected_profit,
+                token.top_bid.as_ref().and_then(|top_bid| top_bid.fill_probability),
+            ) {
+                profit.push_str(&format!(
+                    " (expected {} ETH at {:.0}% fill probability)",
+                    expected_profit,
+                    fill_probability * 100f64
+                ));
+            }
+
+            if let Some(market_profit) = token.market_profit {
+                profit.push_str(&format!(" at your cost basis ({} ETH at market)", market_profit));
+            }
+
+            if token.no_data {
+                profit.push_str("\n⚠️ No data from any price source — do not trust profit");
+            }
+
+            if let Some(fee_breakdown) = token.fee_breakdown {
+                profit.push_str(&format!("\n{}", fee_breakdown));
+            }
+
+            if let (true, Some(breakeven)) = (self.show_breakeven, token.breakeven) {
+                profit.push_str(&format!(
+                    "\nBreakeven MuToken-derived price: {} ETH",
+                    breakeven.round_dp(6).normalize()
+                ));
+            }
+
+            if let Some(bid_ratio_flag) = token.bid_ratio_flag {
+                profit.push_str(&format!("\n{}", bid_ratio_flag));
+            }
+
+            let links = token
+                .links
+                .iter()
+                .map(|(name, url)| format!(r#"<a href="{url}">{name}</a>"#))
+                .collect::<Vec<_>>()
+                .join(" -- ");
+
+            let mut label = token.token_id.to_string();
+            if let Some(name) = &token.name {
+                // names are free text from the collection, so keep them from breaking the html
+                let name = name
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;");
+                label.push_str(&format!(" — {}", name));
+            }
+            if let Some(vault_status) = token.vault_status {
+                label.push_str(&format!(" ({})", vault_status));
+            }
+
             message.push_str(&formatdoc!(
                 r#"
-                Token {0}: <a href="{1}">Blur</a> -- <a href="{2}">Flooring</a> -- <a href="{3}">OpenSea Pro</a>
-                {4}
-                {5}
-                Estimated Arbitrage Profit: {6} ETH
+                Token {0}: {1}
+                {2}
+                {3}
+                Estimated Arbitrage Profit ({5}): {4}
 
                 "#,
-                token.token_id,
-                token.blur_link,
-                token.flooring_link,
-                token.opensea_pro_link,
+                label,
+                links,
                 valuation,
-                token.top_bid,
-                token.profit.unwrap()
+                top_bid,
+                profit,
+                self.sell_price_basis
+            ));
+        }
+
+        if collapsed > 0 {
+            message.push_str(&format!(
+                "and {} more tokens (total {:+} ETH)\n",
+                collapsed, collapsed_profit
+            ));
+        }
+
+        if hidden > 0 {
+            message.push_str(&format!(
+                "{} tokens with profit below {} ETH hidden\n",
+                hidden,
+                self.hide_below.unwrap_or_default()
             ));
         }
 
+        if let Some(raw_log) = &self.raw_log {
+            message.push_str(&format!("\n<code>{}</code>\n", raw_log));
+        }
+
         write!(f, "{}", message)?;
 
         Ok(())
@@ -97,11 +1003,24 @@ impl Display for Message {
 
 impl Display for Valuation {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let message = formatdoc!(
-            r#"DeepNFTValue valuation: <a href="{0}"> {1} ETH </a>"#,
-            self.url,
-            self.price,
-        );
+        let mut message = if self.url.is_empty() {
+            format!("{} valuation: {} ETH", self.source, self.price)
+        } else {
+            formatdoc!(
+                r#"{0} valuation: <a href="{1}"> {2} ETH </a>"#,
+                self.source,
+                self.url,
+                self.price,
+            )
+        };
+
+        if let Some(updated_at) = self.updated_at {
+            message.push_str(&format!(" ({} old)", format_age(Utc::now() - updated_at)));
+        }
+
+        if self.stale {
+            message.push_str(" ⚠️ stale");
+        }
 
         write!(f, "{}", message)?;
 
@@ -111,224 +1030,1676 @@ impl Display for Valuation {
 
 impl Display for TopBid {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let native = match &self.native {
+            Some((amount, symbol)) => format!("{} {} ≈ ", amount, symbol),
+            None => String::new(),
+        };
         let message = formatdoc!(
-            r#"Top Bid (including fees): <a href={0}> {2} ETH on {1} </a>"#,
+            r#"{4} (including fees): <a href={0}> {3}{2} ETH on {1} </a>"#,
             self.url,
             self.kind,
             self.price,
+            native,
+            if self.floor_bid {
+                "Collection Floor Bid"
+            } else {
+                "Top Bid"
+            },
         );
 
         write!(f, "{}", message)?;
 
+        match (self.scope, self.quantity) {
+            (Some(scope), Some(quantity)) if quantity > 1 => {
+                write!(f, " ({}, {} wanted)", scope, quantity)?
+            }
+            (Some(scope), _) => write!(f, " ({})", scope)?,
+            (None, _) => {}
+        }
+
+        if self.excluded > 0 {
+            write!(
+                f,
+                " ({} higher bids from other sources excluded)",
+                self.excluded
+            )?;
+        }
+
+        if self.currency_mismatch {
+            write!(
+                f,
+                "\n⚠️ Bid currency conversion looks off, check the bid before trusting the profit"
+            )?;
+        }
+
+        if let (true, Some(blur_price)) = (self.blur_discrepancy, self.blur_price) {
+            write!(
+                f,
+                "\n⚠️ Blur reports a top bid of {} ETH, Reservoir may be lagging",
+                blur_price
+            )?;
+        }
+
         Ok(())
     }
 }
 
-impl Display for MuToken {
+/// A `Message` as a single line for fast-scrolling feeds: the collection, the total and the most
+/// profitable token, linked to its first marketplace or else to the transaction.
+pub(crate) struct Compact<'a>(&'a Message);
+
+impl Display for Compact<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let message = formatdoc!(
-            r#"{1} Derived Price: <a href="{0}"> {2} ETH </a>"#,
-            self.dexscreener_link,
-            self.name,
-            self.derived_price,
-        );
+        let msg = self.0;
+        let collection = msg
+            .collection_header
+            .trim()
+            .trim_start_matches("Collection: ");
+
+        write!(
+            f,
+            "{}: {} ETH{}",
+            collection,
+            msg.total_profit,
+            format_pct(msg.total_profit_pct)
+        )?;
+
+        let best = msg
+            .tokens
+            .iter()
+            .filter_map(|token| Some((token, token.profit?)))
+            .max_by_key(|(_, profit)| *profit)
+            .map(|(token, _)| token);
+
+        if let Some(best) = best {
+            write!(
+                f,
+                ", best #{} at {} ETH",
+                best.token_id,
+                best.profit.unwrap_or_default()
+            )?;
+        }
+
+        let link = best
+            .and_then(|token| token.links.first())
+            .map(|(name, url)| (name.as_str(), url.as_str()))
+            .unwrap_or(("TX", msg.etherscan_link.as_str()));
+
+        if !link.1.is_empty() {
+            write!(f, r#" <a href="{}">{}</a>"#, link.1, link.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for LastSale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LastSale::Never => write!(f, "Last sale: never sold"),
+            LastSale::Sold {
+                price,
+                timestamp: Some(timestamp),
+            } => write!(
+                f,
+                "Last sale: {} ETH ({} ago)",
+                price,
+                format_age(Utc::now() - *timestamp)
+            ),
+            LastSale::Sold {
+                price,
+                timestamp: None,
+            } => write!(f, "Last sale: {} ETH", price),
+        }
+    }
+}
+
+impl LastSale {
+    /// Read Reservoir's `lastSale`, which is missing or null for a token that never sold.
+    /// `None` when there is a sale but its price can't be read.
+    fn from_json(last_sale: &serde_json::Value) -> Option<Self> {
+        if last_sale.is_null() {
+            return Some(LastSale::Never);
+        }
+
+        Some(LastSale::Sold {
+            price: parse_decimal(&last_sale["price"]["amount"]["native"]).ok()?,
+            timestamp: parse_timestamp(&last_sale["timestamp"]),
+        })
+    }
+}
+
+impl TopBid {
+    fn is_blur(&self) -> bool {
+        self.kind.contains("Blur")
+    }
+
+    pub(crate) fn price(&self) -> Decimal {
+        self.price
+    }
+
+    /// Lowercase source name, without the quotes `kind` is stored with.
+    fn source(&self) -> String {
+        self.kind.trim_matches('"').to_lowercase()
+    }
+}
+
+impl Display for CollectionStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // missing stats are shown as "?" rather than dropping the whole line
+        let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "?".to_string());
+
+        let message = formatdoc!(
+            r#"24h Volume: {0} ETH -- Listings: {1} -- Floor: {2} ETH"#,
+            or_unknown(self.volume_1day.map(|v| v.to_string())),
+            or_unknown(self.listings.map(|v| v.to_string())),
+            or_unknown(self.floor.map(|v| v.to_string())),
+        );
 
         write!(f, "{}", message)?;
 
-        Ok(())
+        Ok(())
+    }
+}
+
+impl Display for TxDetails {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let message = formatdoc!(
+            r#"Gas used: {0} -- Method: {1} on {2}"#,
+            self.gas_used,
+            self.method_id,
+            self.to,
+        );
+
+        write!(f, "{}", message)?;
+
+        Ok(())
+    }
+}
+
+impl Display for MuToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut message = formatdoc!(
+            r#"{1} Derived Price: <a href="{0}"> {2} ETH </a>"#,
+            self.dexscreener_link,
+            self.name,
+            self.derived_price,
+        );
+
+        if let Some(cost_basis) = self.cost_basis {
+            message.push_str(&format!(" -- Your Cost Basis: {} ETH", cost_basis));
+        }
+
+        if let Some(liquidity_usd) = self.liquidity_usd {
+            message.push_str(&format!(" -- Liquidity: ${}", liquidity_usd.round_dp(0)));
+        }
+
+        if self.illiquid {
+            message.push_str("\n⚠️ Low MuToken liquidity, the derived price may not be tradable");
+        }
+
+        if let Some(identity_warning) = &self.identity_warning {
+            message.push_str(&format!(
+                "\n⚠️ MuToken identity uncertain: {}",
+                identity_warning
+            ));
+        }
+
+        write!(f, "{}", message)?;
+
+        Ok(())
+    }
+}
+
+impl Message {
+    pub(crate) async fn fill_message(
+        mut self,
+        log: FragmentNftFilter,
+        meta: LogMeta,
+        state: &AppState,
+    ) -> Self {
+        let tx_hash: String = format!("{:#x}", meta.transaction_hash);
+        self.tx_hash = tx_hash.clone();
+
+        // for debugging new collections, show the event fields as decoded rather than as
+        // re-derived from the transaction
+        if crate::config::var_or("INCLUDE_RAW_LOG", false) {
+            self.raw_log = Some(raw_log(&log, &meta));
+        }
+
+        // create a link to the transaction on the chain's block explorer
+        self.etherscan_link = state.chain.tx_link(&tx_hash);
+
+        // only enrich with tx details when an etherscan api key is configured, and only on
+        // Ethereum, the one chain the etherscan api answers for
+        if let (Ok(api_key), 1) = (dotenv::var("ETHERSCAN_API_KEY"), state.chain.chain_id) {
+            self.tx_details = match Self::get_tx_details(&tx_hash, &api_key).await {
+                Ok(tx_details) => Some(tx_details),
+                Err(e) => self.note_error("tx details", e),
+            };
+        }
+
+        // tells real time alerts from backfilled ones, and shows when the monitor lags
+        if crate::config::var_or("INCLUDE_CONFIRMATIONS", false) {
+            let block = meta.block_number.as_u64();
+            match state.flooring.client().get_block_number().await {
+                Ok(head) => {
+                    let confirmations = head.as_u64().saturating_sub(block);
+                    debug!(
+                        "Event in block {} is {} blocks behind head",
+                        block, confirmations
+                    );
+                    self.confirmations = Some((block, confirmations));
+                }
+                Err(e) => error!("Error getting chain head: {:?}", e),
+            }
+        }
+
+        // a repeated id would be priced and counted towards the total profit twice
+        let mut token_ids = log.token_ids;
+        let duplicates = drop_duplicates(&mut token_ids);
+        if duplicates > 0 {
+            warn!(
+                "Dropped {} duplicate token ids from {}",
+                duplicates, tx_hash
+            );
+        }
+
+        self.fill_tokens(state, log.collection, token_ids).await
+    }
+
+    /// An on demand quote for one token, priced the same way as an event but without a
+    /// transaction.
+    pub(crate) async fn quote(state: &AppState, collection: Address, token_id: U256) -> Self {
+        Message::default()
+            .fill_tokens(state, collection, vec![token_id])
+            .await
+    }
+
+    /// Everything about the collection and each of `token_ids`, shared by events and quotes.
+    async fn fill_tokens(
+        mut self,
+        state: &AppState,
+        collection: Address,
+        token_ids: Vec<U256>,
+    ) -> Self {
+        let collection_address: String = format!("{:#x}", collection);
+        self.collection = collection_address.clone();
+
+        self.collection_header = match self.slug(state, &collection_address).await {
+            Some(slug) => format! {"\nCollection: {}", slug},
+            None => format! {"\nCollection: {collection_address}"},
+        };
+        if state.multi_chain {
+            self.collection_header
+                .push_str(&format!(" on {}", state.chain.name));
+        }
+
+        if crate::config::var_or("INCLUDE_COLLECTION_STATS", false) {
+            self.collection_stats = match Self::get_collection_stats(&collection_address).await {
+                Ok(collection_stats) => Some(collection_stats),
+                Err(e) => self.note_error("collection stats", e),
+            };
+        }
+
+        self.mu_token = match self
+            .get_mu_token_details(state, &collection_address)
+            .await
+        {
+            Ok(mu_token) => Some(mu_token),
+            // not an upstream failure, the collection just can't be arbitraged
+            Err(e @ MessageError::NoMuToken { .. }) => {
+                warn!("{}", e);
+                self.no_mu_token = true;
+                None
+            }
+            Err(e) => self.note_error("MuToken details", e),
+        };
+
+        self.sell_price_basis = SellPriceBasis::from_env();
+        self.fees = Fees::from_env(&collection_address);
+        self.show_breakeven = crate::config::var_or("SHOW_BREAKEVEN", true);
+        self.hide_below = dotenv::var("HIDE_TOKENS_BELOW_PROFIT")
+            .ok()
+            .and_then(|v| v.parse::<Decimal>().ok());
+        self.max_detailed = crate::config::var_opt("MAX_DETAILED_TOKENS");
+        let floor_bid_fallback = FloorBidFallback::from_env();
+        let floor_bid = match floor_bid_fallback {
+            FloorBidFallback::Off => None,
+            _ => match Self::get_floor_bid(&collection_address).await {
+                Ok(floor_bid) => floor_bid,
+                Err(e) => self.note_error("floor bid", e),
+            },
+        };
+        let options = TokenOptions {
+            no_data_policy: NoDataPolicy::from_env(),
+            marketplace_links: MarketplaceLink::from_env()
+                .into_iter()
+                .chain(MarketplaceLink::flooring_action())
+                .collect(),
+            fetch_images: dotenv::var("DISCORD_WEBHOOK_URL").is_ok(),
+            fetch_last_sale: crate::config::var_or("INCLUDE_LAST_SALE", false),
+            resolve_token_uri: crate::config::var_or("RESOLVE_TOKEN_URI", false),
+            vault_check: VaultCheck::from_env(),
+            floor_bid_fallback,
+            floor_bid,
+        };
+
+        // several tokens at once, so an event's requests interleave with other events' under
+        // `API_CONCURRENCY`. Tokens keep their emitted order either way.
+        let concurrency = crate::config::var_or("TOKEN_CONCURRENCY", 4usize).max(1);
+        let built = futures::stream::iter(token_ids)
+            .map(|token_id| {
+                let token = self.build_token(state, &collection_address, token_id, &options);
+                isolate_panic(token_id, token)
+            })
+            .buffered(concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        for (token, errors) in built {
+            self.errors.extend(errors);
+            self.tokens.extend(token);
+        }
+
+        if let Some(blur) = BlurConfig::from_env() {
+            self.apply_blur_bids(&blur).await;
+        }
+
+        self.check_bid_ratios(BidRatioBounds::from_env());
+
+        self.compute_total_profit();
+
+        if crate::config::var_or("INCLUDE_PULLED_BID_RISK", false) {
+            self.pulled_bid_risk = self.get_pulled_bid_risk().await;
+        }
+
+        // purely presentational, the totals don't depend on it
+        TokenSort::from_env().apply(&mut self.tokens);
+
+        self
+    }
+
+    /// Price unwinding one NFT at the collection's floor ask, or at the MuToken derived price when
+    /// the floor is unknown, using the collection stats when they were already fetched.
+    async fn get_pulled_bid_risk(&mut self) -> Option<PulledBidRisk> {
+        let mu_token = self.mu_token.clone()?;
+
+        let floor = match &self.collection_stats {
+            Some(collection_stats) => collection_stats.floor,
+            None => match Self::get_collection_stats(&self.collection).await {
+                Ok(collection_stats) => collection_stats.floor,
+                Err(e) => self.note_error("collection floor", e),
+            },
+        };
+
+        let (basis, unwind_price) = match floor.and_then(Decimal::from_f64) {
+            Some(floor) => ("floor", floor),
+            None => ("MuToken derived price", mu_token.derived_price()),
+        };
+        let cost = mu_token.cost();
+
+        Some(PulledBidRisk {
+            basis,
+            unwind_price,
+            loss: cost + self.fees.breakdown(unwind_price, cost).total() - unwind_price,
+        })
+    }
+
+    /// Fetch everything for one token and work out its profit, with the upstream errors hit on
+    /// the way. `None` means the token was filtered out.
+    async fn build_token(
+        &self,
+        state: &AppState,
+        collection_address: &str,
+        token_id: U256,
+        options: &TokenOptions,
+    ) -> (Option<Token>, Vec<(ApiProvider, ErrorCategory)>) {
+        let mut errors = Vec::new();
+
+        let valuation = match self.get_valuation(state, collection_address, token_id).await {
+            Ok(valuation) => valuation,
+            Err(e) => record_error(&mut errors, "valuation", e),
+        };
+
+        let top_bid = match self.get_top_bid(collection_address, token_id).await {
+            Ok(top_bid) => Some(top_bid),
+            // per-token bids are often just missing, which the floor bid covers
+            Err(e) if options.floor_bid.is_some() => {
+                info!(
+                    "No top bid for token {}, using the floor bid: {}",
+                    token_id, e
+                );
+                None
+            }
+            Err(e @ MessageError::NoData { .. }) => {
+                info!("No top bid for token {}: {}", token_id, e);
+                None
+            }
+            Err(e) => record_error(&mut errors, "top bid", e),
+        };
+        let top_bid = options
+            .floor_bid_fallback
+            .choose(top_bid, options.floor_bid.as_ref());
+
+        let mut token = Token {
+            token_id,
+            links: options
+                .marketplace_links
+                .iter()
+                .map(|link| (link.name.clone(), link.url(collection_address, token_id)))
+                .collect(),
+            valuation,
+            top_bid,
+            profit: None,
+            market_profit: None,
+            profit_pct: None,
+            expected_profit: None,
+            fee_breakdown: None,
+            breakeven: None,
+            no_data: false,
+            image: None,
+            name: None,
+            last_sale: None,
+            vault_status: None,
+            bid_ratio_flag: None,
+        };
+
+        token.compute_profit(self.mu_token.as_ref(), self.sell_price_basis, &self.fees);
+
+        if options.vault_check != VaultCheck::Off {
+            let vault_status =
+                match Self::get_vault_status(state, collection_address, token_id).await {
+                    Ok(vault_status) => vault_status,
+                    Err(e) => {
+                        record_error::<()>(&mut errors, "vault status", e);
+                        VaultStatus::Unknown
+                    }
+                };
+
+            // unknown is kept, since a failed call says nothing about the token
+            if options.vault_check == VaultCheck::Filter
+                && matches!(
+                    vault_status,
+                    VaultStatus::Locked { .. } | VaultStatus::NotInVault
+                )
+            {
+                info!("Skipping token {}: {}", token_id, vault_status);
+                return (None, errors);
+            }
+
+            token.vault_status = Some(vault_status);
+        }
+
+        // image and last sale come from the same Reservoir token lookup
+        if options.fetch_images || options.fetch_last_sale {
+            match Self::get_reservoir_token(collection_address, token_id).await {
+                Ok(reservoir_token) => {
+                    if options.fetch_images {
+                        token.image = reservoir_token["image"]
+                            .as_str()
+                            .map(|image| image.to_string());
+                    }
+                    if options.fetch_last_sale {
+                        token.last_sale = LastSale::from_json(&reservoir_token["lastSale"]);
+                    }
+                }
+                Err(e) => {
+                    record_error::<()>(&mut errors, "Reservoir token", e);
+                }
+            }
+        }
+
+        // long tail collections Reservoir has no metadata for still have a tokenURI
+        if options.resolve_token_uri && token.image.is_none() {
+            match crate::metadata::resolve(state, collection_address, token_id).await {
+                Ok(metadata) => {
+                    token.name = metadata.name;
+                    token.image = metadata.image;
+                }
+                Err(e) => {
+                    record_error::<()>(&mut errors, "token metadata", e);
+                }
+            }
+        }
+
+        if !token.apply_no_data_policy(self.mu_token.is_some(), options.no_data_policy) {
+            info!("Skipping token {}: no data from any price source", token_id);
+            return (None, errors);
+        }
+
+        (Some(token), errors)
+    }
+
+    /// Re-fetch the MuToken price and every top bid and recompute profit, to confirm an
+    /// opportunity still stands right before alerting on it. Anything that can't be re-fetched
+    /// is dropped rather than trusted.
+    pub(crate) async fn reconfirm(mut self, state: &AppState) -> Self {
+        let collection = self.collection.clone();
+
+        self.mu_token = match self.get_mu_token_details(state, &collection).await {
+            Ok(mu_token) => Some(mu_token),
+            Err(e) => self.note_error("MuToken details re-check", e),
+        };
+
+        let token_ids = self.tokens.iter().map(|token| token.token_id).collect::<Vec<_>>();
+        let mut top_bids = Vec::with_capacity(token_ids.len());
+        for token_id in token_ids {
+            top_bids.push(match self.get_top_bid(&collection, token_id).await {
+                Ok(top_bid) => Some(top_bid),
+                Err(e) => self.note_error("top bid re-check", e),
+            });
+        }
+
+        for (token, top_bid) in self.tokens.iter_mut().zip(top_bids) {
+            token.top_bid = top_bid;
+            token.compute_profit(self.mu_token.as_ref(), self.sell_price_basis, &self.fees);
+        }
+
+        if let Some(blur) = BlurConfig::from_env() {
+            self.apply_blur_bids(&blur).await;
+        }
+
+        self.check_bid_ratios(BidRatioBounds::from_env());
+
+        self.compute_total_profit();
+
+        self
+    }
+
+    /// Cross-check every Blur-sourced top bid against Blur's own bid book, which Reservoir can
+    /// lag behind. Blur bids are collection wide, so one lookup covers every token.
+    async fn apply_blur_bids(&mut self, blur: &BlurConfig) {
+        let has_blur_bid = self
+            .tokens
+            .iter()
+            .any(|token| token.top_bid.as_ref().is_some_and(TopBid::is_blur));
+        if !has_blur_bid {
+            return;
+        }
+
+        let blur_price = match blur.top_bid(&self.collection).await {
+            Ok(blur_price) => blur_price,
+            Err(e) => {
+                self.note_error::<()>("Blur top bid", e);
+                return;
+            }
+        };
+
+        for token in self.tokens.iter_mut() {
+            let Some(top_bid) = token.top_bid.as_mut().filter(|top_bid| top_bid.is_blur()) else {
+                continue;
+            };
+
+            top_bid.blur_price = Some(blur_price);
+            top_bid.blur_discrepancy = percent_of(blur_price - top_bid.price, top_bid.price)
+                .is_some_and(|pct| pct.abs() > blur.discrepancy_pct);
+
+            if blur.mode == BlurBidMode::Replace {
+                top_bid.price = blur_price;
+                // Blur's price is in ETH, so Reservoir's amount no longer applies
+                top_bid.native = None;
+                top_bid.currency_mismatch = false;
+                // Blur's executable bids are collection wide
+                top_bid.scope = Some(BidScope::Collection);
+                top_bid.quantity = None;
+            }
+
+            token.compute_profit(self.mu_token.as_ref(), self.sell_price_basis, &self.fees);
+        }
+    }
+
+    /// Flag tokens whose top bid is out of line with their valuation, dropping suspiciously
+    /// high ones when the policy says to.
+    fn check_bid_ratios(&mut self, bounds: BidRatioBounds) {
+        for token in self.tokens.iter_mut() {
+            token.bid_ratio_flag = bounds.check(token);
+        }
+
+        if bounds.suppress {
+            self.tokens.retain(|token| {
+                let suspicious =
+                    matches!(token.bid_ratio_flag, Some(BidRatioFlag::AboveValuation(_)));
+                if suspicious {
+                    info!("Dropping token {}: suspicious bid", token.token_id);
+                }
+                !suspicious
+            });
+        }
+    }
+
+    /// Log an upstream error and remember its provider and category for the metrics.
+    fn note_error<T>(&mut self, what: &str, e: MessageError) -> Option<T> {
+        record_error(&mut self.errors, what, e)
+    }
+
+    /// Sum the profit of every token with a positive profit.
+    fn compute_total_profit(&mut self) {
+        self.total_profit = self
+            .tokens
+            .iter()
+            .filter_map(|token| token.profit)
+            .filter(|profit| *profit > Decimal::ZERO)
+            .sum();
+
+        self.expected_total_profit = self
+            .tokens
+            .iter()
+            .filter_map(|token| token.expected_profit)
+            .filter(|profit| *profit > Decimal::ZERO)
+            .sum();
+
+        let profitable = self
+            .tokens
+            .iter()
+            .filter(|token| token.profit.is_some_and(|profit| profit > Decimal::ZERO))
+            .count();
+        self.total_profit_pct = self.mu_token.as_ref().and_then(|mu_token| {
+            percent_of(self.total_profit, mu_token.cost() * Decimal::from(profitable))
+        });
+    }
+
+    /// One CSV row per token, matching `CSV_HEADER`. Missing figures are left empty.
+    pub(crate) fn csv_rows(&self, timestamp: u64) -> Vec<String> {
+        let or_empty = |value: Option<Decimal>| value.map(|v| v.to_string()).unwrap_or_default();
+
+        self.tokens
+            .iter()
+            .map(|token| {
+                format!(
+                    "{},{},{},{},{},{},{},{}",
+                    timestamp,
+                    self.tx_hash,
+                    self.collection,
+                    token.token_id,
+                    or_empty(self.mu_token.as_ref().map(|mu_token| mu_token.derived_price)),
+                    or_empty(token.top_bid.as_ref().map(|top_bid| top_bid.price)),
+                    or_empty(token.valuation.as_ref().map(|valuation| valuation.price)),
+                    or_empty(token.profit),
+                )
+            })
+            .collect()
+    }
+
+    /// Discord webhook body: the headline as content and one embed per token, up to Discord's
+    /// embed limit.
+    pub(crate) fn discord_payload(&self) -> serde_json::Value {
+        let embeds = self
+            .tokens
+            .iter()
+            .take(crate::discord::MAX_EMBEDS)
+            .map(|token| {
+                let or_unknown = |value: Option<Decimal>| {
+                    value
+                        .map(|v| format!("{} ETH", v))
+                        .unwrap_or_else(|| "?".to_string())
+                };
+
+                let mut embed = serde_json::json!({
+                    "title": token
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("Token {}", token.token_id)),
+                    "fields": [
+                        {
+                            "name": "Top Bid",
+                            "value": or_unknown(token.top_bid.as_ref().map(|top_bid| top_bid.price)),
+                            "inline": true,
+                        },
+                        {
+                            "name": "Valuation",
+                            "value": or_unknown(token.valuation.as_ref().map(|valuation| valuation.price)),
+                            "inline": true,
+                        },
+                        {
+                            "name": format!("Profit ({})", self.sell_price_basis),
+                            "value": format!("{}{}", or_unknown(token.profit), format_pct(token.profit_pct)),
+                            "inline": true,
+                        },
+                    ],
+                });
+
+                if let Some((_, url)) = token.links.first() {
+                    embed["url"] = url.clone().into();
+                }
+                if let Some(image) = &token.image {
+                    embed["thumbnail"] = serde_json::json!({ "url": image });
+                }
+
+                embed
+            })
+            .collect::<Vec<_>>();
+
+        let mut content = format!(
+            "[TX]({}) with {} ETH{} profit{}",
+            self.etherscan_link,
+            self.total_profit,
+            format_pct(self.total_profit_pct),
+            self.collection_header,
+        );
+        if self.tokens.len() > embeds.len() {
+            content.push_str(&format!(
+                "\n{} more tokens not shown",
+                self.tokens.len() - embeds.len()
+            ));
+        }
+
+        serde_json::json!({ "content": content, "embeds": embeds })
+    }
+
+    /// Whether `token` is left out of the rendered message by `HIDE_TOKENS_BELOW_PROFIT`.
+    fn is_hidden(&self, token: &Token) -> bool {
+        match (self.hide_below, token.profit) {
+            (Some(hide_below), Some(profit)) => profit < hide_below,
+            _ => false,
+        }
+    }
+
+    /// Which of `tokens` are rendered in full: the `max_detailed` most profitable of those not
+    /// hidden, with tokens of unknown profit ranked last.
+    fn detailed_tokens(&self) -> Vec<bool> {
+        let mut detailed = vec![true; self.tokens.len()];
+        let Some(max_detailed) = self.max_detailed else {
+            return detailed;
+        };
+
+        let mut by_profit = (0..self.tokens.len())
+            .filter(|&i| !self.is_hidden(&self.tokens[i]))
+            .collect::<Vec<_>>();
+        by_profit.sort_by_key(|&i| std::cmp::Reverse(self.tokens[i].profit));
+        for &i in by_profit.iter().skip(max_detailed) {
+            detailed[i] = false;
+        }
+
+        detailed
+    }
+
+    /// The alert text, one line per alert with `COMPACT_MODE`, otherwise the full message.
+    pub(crate) fn render(&self) -> String {
+        if crate::config::var_or("COMPACT_MODE", false) {
+            self.compact().to_string()
+        } else {
+            self.to_string()
+        }
+    }
+
+    pub(crate) fn compact(&self) -> Compact<'_> {
+        Compact(self)
+    }
+
+    /// One line linking the transaction with its collection and profit, for digests.
+    pub(crate) fn summary_line(&self) -> String {
+        format!(
+            r#"<a href="{}">TX</a> {}: {} ETH{}"#,
+            self.etherscan_link,
+            self.collection_header.trim(),
+            self.total_profit,
+            format_pct(self.total_profit_pct)
+        )
+    }
+
+    /// The alert as JSON for machine consumers (SSE and NATS): an object with `schema_version`
+    /// (`SCHEMA_VERSION`) next to the `Message` fields, which are
+    ///
+    /// - `collection`, `tx_hash`: lowercase `0x` hex
+    /// - `etherscan_link`, `collection_header`: strings, the header as html
+    /// - `tx_details`, `confirmations` (`[block, blocks behind head]`), `collection_stats`,
+    ///   `mu_token`, `raw_log`: null when not fetched
+    /// - `total_profit`, `expected_total_profit`: ETH, and `total_profit_pct`
+    /// - `tokens`: one object per token, with `token_id` as a decimal string, `valuation`,
+    ///   `top_bid`, `profit` and what else was fetched for it
+    /// - `sell_price_basis`, `fees`: the settings profit was computed with
+    ///
+    /// ETH amounts are decimal strings, and enums are snake case strings.
+    pub(crate) fn to_json(&self) -> serde_json::Result<String> {
+        #[derive(Serialize)]
+        struct Versioned<'a> {
+            schema_version: u32,
+            #[serde(flatten)]
+            msg: &'a Message,
+        }
+
+        serde_json::to_string(&Versioned {
+            schema_version: SCHEMA_VERSION,
+            msg: self,
+        })
+    }
+
+    pub(crate) fn tx_hash(&self) -> &str {
+        &self.tx_hash
+    }
+
+    pub(crate) fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// Whether the MuToken's DEX liquidity is below `MIN_MU_TOKEN_LIQUIDITY_USD`.
+    pub(crate) fn illiquid(&self) -> bool {
+        self.mu_token.as_ref().is_some_and(MuToken::illiquid)
+    }
+
+    /// What one NFT costs in MuTokens, if the MuToken price is known.
+    pub(crate) fn mu_token_cost(&self) -> Option<Decimal> {
+        self.mu_token.as_ref().map(MuToken::cost)
+    }
+
+    /// `(collection, token_id)` for every token in the message.
+    pub(crate) fn token_keys(&self) -> Vec<(String, U256)> {
+        self.tokens
+            .iter()
+            .map(|token| (self.collection.clone(), token.token_id))
+            .collect()
+    }
+
+    pub(crate) async fn get_mu_token_details(
+        &self,
+        state: &AppState,
+        collection: &str,
+    ) -> Result<MuToken, MessageError> {
+        // use ethers RPC to call the `collectionInfo` function on the flooring contract for the given collection
+        let collection_info = Self::get_collection_info(state, collection).await?;
+
+        let mu_token_address = collection_info.0;
+        // nothing below means anything without a MuToken, and its price can't be parsed anyway
+        if mu_token_address.is_zero() {
+            return Err(MessageError::NoMuToken {
+                collection: collection.to_string(),
+            });
+        }
+
+        // a misconfigured or spoofed collection could report an unrelated token
+        let identity_warning = Self::check_mu_token(state, collection, mu_token_address).await;
+        if let Some(identity_warning) = &identity_warning {
+            warn!(
+                "MuToken {:#x} for {} may be wrong: {}",
+                mu_token_address, collection, identity_warning
+            );
+            if matches!(dotenv::var("MU_TOKEN_CHECK").as_deref(), Ok("skip")) {
+                return Err(MessageError::no_data(
+                    ApiProvider::Rpc,
+                    format!("MuToken identity uncertain: {}", identity_warning),
+                ));
+            }
+        }
+
+        let fragments_per_nft =
+            Self::get_fragments_per_nft(state, collection, mu_token_address).await;
+
+        // now get the mu token price from paraswap api
+        let nft_derived_price =
+            Self::get_token_price(state.chain.chain_id, mu_token_address, fragments_per_nft)
+                .await?;
+
+        let mu_token_name = "mu token";
+
+        let dexscreener_link = format!(
+            "https://dexscreener.com/{}/{:#x}",
+            state.chain.name, mu_token_address
+        );
+
+        // a price derived from a near empty pool can't be executed in size
+        let min_liquidity = crate::config::var_opt::<Decimal>("MIN_MU_TOKEN_LIQUIDITY_USD");
+        let liquidity_usd =
+            if min_liquidity.is_some() || crate::config::var_or("INCLUDE_LIQUIDITY", false) {
+                match Self::get_liquidity(&state.chain.name, mu_token_address).await {
+                    Ok(liquidity_usd) => Some(liquidity_usd),
+                    Err(e) => {
+                        warn!("Error getting MuToken liquidity: {:?}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+        Ok(MuToken {
+            dexscreener_link,
+            name: mu_token_name.to_string(),
+            derived_price: nft_derived_price,
+            cost_basis: crate::config::per_collection::<Decimal>("MU_TOKEN_COST_BASIS")
+                .get(collection)
+                .copied(),
+            liquidity_usd,
+            identity_warning,
+            illiquid: matches!(
+                (liquidity_usd, min_liquidity),
+                (Some(liquidity_usd), Some(min_liquidity)) if liquidity_usd < min_liquidity
+            ),
+        })
+    }
+
+    /// Total USD liquidity of `address`'s DEX pools on `chain`, from DexScreener.
+    async fn get_liquidity(chain: &str, address: Address) -> Result<Decimal, MessageError> {
+        let client = crate::http::client();
+
+        let url = format!(
+            "https://api.dexscreener.com/latest/dex/tokens/{:#x}",
+            address
+        );
+
+        let req = client.get(url).header("accept", "application/json");
+
+        let json = get_json(ApiProvider::DexScreener, req).await?;
+
+        // no pairs at all is a real answer, the token simply has no pools
+        Ok(json["pairs"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|pair| pair["chainId"].as_str() == Some(chain))
+            .filter_map(|pair| parse_decimal(&pair["liquidity"]["usd"]).ok())
+            .sum())
     }
-}
 
-impl Message {
-    pub(crate) async fn fill_message(mut self, log: FragmentNftFilter, meta: LogMeta) -> Self {
-        let tx_hash: String = format!("{:#x}", meta.transaction_hash);
-        let collection_address: String = format!("{:#x}", log.collection);
+    /// Why `mu_token_address` may not be `collection`'s MuToken, `None` when it checks out. A
+    /// `MU_TOKEN_REGISTRY` entry for the collection must match exactly, otherwise the token's
+    /// symbol must start with `MU_TOKEN_SYMBOL_PREFIX` (default "μ"), as Flooring's do.
+    /// `MU_TOKEN_CHECK=off` skips the check.
+    async fn check_mu_token(
+        state: &AppState,
+        collection: &str,
+        mu_token_address: Address,
+    ) -> Option<String> {
+        if matches!(dotenv::var("MU_TOKEN_CHECK").as_deref(), Ok("off")) {
+            return None;
+        }
 
-        // create a link to the transaction on etherscan
-        self.etherscan_link = format!("https://etherscan.io/tx/{tx_hash}");
+        if let Some(expected) =
+            crate::config::per_collection::<Address>("MU_TOKEN_REGISTRY").get(collection)
+        {
+            return (*expected != mu_token_address)
+                .then(|| format!("the registry expects {:#x}", expected));
+        }
 
-        self.collection_header = match self.slug(&collection_address).await {
-            Some(slug) => format! {"\nCollection: {}", slug},
-            None => format! {"\nCollection: {collection_address}"},
+        let cached = state
+            .mu_token_symbols
+            .lock()
+            .unwrap()
+            .get(&mu_token_address)
+            .cloned();
+        let symbol = match cached {
+            Some(symbol) => symbol,
+            None => {
+                let fragment_token =
+                    crate::FragmentToken::new(mu_token_address, state.flooring.client());
+                match fragment_token.symbol().await {
+                    Ok(symbol) => {
+                        state
+                            .mu_token_symbols
+                            .lock()
+                            .unwrap()
+                            .insert(mu_token_address, symbol.clone());
+                        symbol
+                    }
+                    Err(e) => {
+                        error!("Error reading MuToken symbol for {collection}: {:?}", e);
+                        return Some("its symbol could not be read".to_string());
+                    }
+                }
+            }
         };
 
-        self.mu_token = self.get_mu_token_details(&collection_address).await;
-
-        // create links for each token id
-        for token_id in log.token_ids {
-            let mut token = Token {
-                token_id,
-                blur_link: format!("https://blur.io/asset/{collection_address}/{}", token_id),
-                flooring_link: format!(
-                    "https://www.flooring.io/nft-details/{collection_address}/{}",
-                    token_id
-                ),
-                opensea_pro_link: format!(
-                    "https://pro.opensea.io/nft/{collection_address}/{}",
-                    token_id
-                ),
-                valuation: self.get_valuation(&collection_address, token_id).await,
-                top_bid: self.get_top_bid(&collection_address, token_id).await,
-                profit: None,
-            };
+        let prefix = dotenv::var("MU_TOKEN_SYMBOL_PREFIX").unwrap_or_else(|_| "μ".to_string());
+        (!symbol.starts_with(&prefix))
+            .then(|| format!("its symbol {} doesn't start with {}", symbol, prefix))
+    }
 
-            token.profit = Some(token.top_bid.price - self.mu_token.derived_price);
+    /// `collectionInfo` for `collection`, served from cache while fresh.
+    pub(crate) async fn get_collection_info(
+        state: &AppState,
+        collection: &str,
+    ) -> Result<CollectionInfo, MessageError> {
+        let cache = &state.collection_info;
 
-            self.tokens.push(token);
+        if let Some(collection_info) = cache.get(&collection.to_string()) {
+            return Ok(collection_info);
         }
 
-        // calculate total profit, only including tokens with positive profit
-        self.total_profit = self
-            .tokens
-            .iter()
-            .filter(|token| token.profit.unwrap() > 0f64)
-            .map(|token| token.profit.unwrap())
-            .sum();
+        let collection = collection
+            .parse::<H160>()
+            .map_err(|e| MessageError::parse(ApiProvider::Rpc, e))?;
+        let collection_info = state
+            .flooring
+            .collection_info(collection)
+            .await
+            .map_err(MessageError::rpc)?;
+        cache.insert(format!("{:#x}", collection), collection_info);
 
-        self
+        Ok(collection_info)
     }
 
-    pub(crate) async fn get_mu_token_details(&self, collection: &str) -> MuToken {
-        // use ethers RPC to call the `collectionInfo` function on the flooring contract for the given collection
-
-        let client = crate::get_http_client().await;
+    /// Read where `token_id` is relative to the vault.
+    ///
+    /// `ownerOf` on the collection tells whether the vault still holds the token at all. If it
+    /// does, `getSafeBox(collection, nftId)` on the Flooring getter tells free NFTs (a zero
+    /// `keyId`) from ones locked in a safebox, whose `expiryTs` says how long the lock lasts. Fragmenting always leaves the NFT free, so
+    /// anything else means it changed hands between the event and this check. A reverting
+    /// `ownerOf`, as for a burned or nonexistent token, is an error rather than `NotInVault`.
+    /// `collectionInfo().freeNftLength` only counts free NFTs per collection, so it can't answer
+    /// this for a single token.
+    pub(crate) async fn get_vault_status(
+        state: &AppState,
+        collection: &str,
+        token_id: U256,
+    ) -> Result<VaultStatus, MessageError> {
+        let collection = collection
+            .parse::<H160>()
+            .map_err(|e| MessageError::parse(ApiProvider::Rpc, e))?;
 
-        let flooring = crate::FlooringInterface::new(
-            "0x8ad7892f15e6a3a1c0eecf83c30f414227434540"
-                .parse::<H160>()
-                .unwrap(),
-            client.into(),
-        );
+        let owner = crate::Erc721::new(collection, state.flooring.client())
+            .owner_of(token_id)
+            .await
+            .map_err(MessageError::rpc)?;
+        if !state.chain.flooring.contains(&owner) {
+            return Ok(VaultStatus::NotInVault);
+        }
 
-        let collection_info = flooring
-            .collection_info(collection.parse::<H160>().unwrap())
+        // (keyId, expiryTs, owner)
+        let (key_id, expiry, _) = state
+            .flooring
+            .get_safe_box(collection, token_id)
             .await
-            .unwrap();
+            .map_err(MessageError::rpc)?;
 
-        let mu_token_address = collection_info.0;
+        Ok(match key_id {
+            0 => VaultStatus::Free,
+            _ => VaultStatus::Locked { expiry },
+        })
+    }
 
-        // now get the mu token price from paraswap api
-        let nft_derived_price = Self::get_token_price(mu_token_address).await.unwrap();
+    /// Read how many raw fragment tokens back a single NFT of `collection`.
+    ///
+    /// Every free (fragmented) NFT in the vault is backed by the same amount of fragment token, so
+    /// this is the fragment token's `totalSupply()` divided by `collectionInfo().freeNftLength`.
+    /// Both are read fresh rather than from `state.collection_info`, as a cached length next to a
+    /// live supply skews the ratio. When the vault is empty or a call fails we fall back to
+    /// `DEFAULT_FRAGMENTS_PER_NFT` without caching, so the next event tries again.
+    pub(crate) async fn get_fragments_per_nft(
+        state: &AppState,
+        collection: &str,
+        mu_token_address: Address,
+    ) -> U256 {
+        let cache = &state.fragments_per_nft;
 
-        let mu_token_name = "mu token";
+        if let Some(fragments_per_nft) = cache.get(&collection.to_string()) {
+            return fragments_per_nft;
+        }
+
+        let collection_info = match collection.parse::<H160>() {
+            Ok(collection_address) => state
+                .flooring
+                .collection_info(collection_address)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let free_nft_length = match collection_info {
+            Ok(collection_info) => collection_info.1,
+            Err(e) => {
+                error!("Error reading free NFTs for {collection}: {}", e);
+                return U256::from(DEFAULT_FRAGMENTS_PER_NFT);
+            }
+        };
 
-        let dexscreener_link = format!("https://dexscreener.com/ethereum/{:#x}", mu_token_address);
+        if free_nft_length.is_zero() {
+            info!("No free NFTs for {collection}, using default fragments per NFT");
+            return U256::from(DEFAULT_FRAGMENTS_PER_NFT);
+        }
 
-        MuToken {
-            dexscreener_link,
-            name: mu_token_name.to_string(),
-            derived_price: nft_derived_price,
+        let fragment_token = crate::FragmentToken::new(mu_token_address, state.flooring.client());
+
+        match fragment_token.total_supply().await {
+            Ok(total_supply) => {
+                let fragments_per_nft = total_supply / free_nft_length;
+                cache.insert(collection.to_string(), fragments_per_nft);
+                fragments_per_nft
+            }
+            Err(e) => {
+                error!("Error reading fragment token supply for {collection}: {:?}", e);
+                U256::from(DEFAULT_FRAGMENTS_PER_NFT)
+            }
         }
     }
 
-    pub(crate) async fn get_token_price(address: Address) -> eyre::Result<f64> {
+    pub(crate) async fn get_token_price(
+        chain_id: u64,
+        address: Address,
+        fragments_per_nft: U256,
+    ) -> Result<Decimal, MessageError> {
         let address = format!("{:#x}", address);
         
-        let url = format! {"https://api.paraswap.io/prices/?srcToken=0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE&destToken={}&amount=1000000000000000000000000&srcDecimals=18&destDecimals=18&side=BUY&excludeDirectContractMethods=false&network=1&otherExchangePrices=true&partner=paraswap.io&userAddress=0x0000000000000000000000000000000000000000", address};
+        let url = format! {"https://api.paraswap.io/prices/?srcToken=0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE&destToken={}&amount={}&srcDecimals=18&destDecimals=18&side=BUY&excludeDirectContractMethods=false&network={}&otherExchangePrices=true&partner=paraswap.io&userAddress=0x0000000000000000000000000000000000000000", address, fragments_per_nft, chain_id};
 
-        let client = reqwest::Client::new();
+        let client = crate::http::client();
 
         let req = client
             .get(url)
             .header("accept", "application/json");
-        
-        let response = req.send().await?;
 
         // get json from response
-        let json = response.json::<serde_json::Value>().await?;
+        let json = get_json(ApiProvider::ParaSwap, req).await?;
+
+        // no route at all, e.g. no liquidity, comes back as an error message instead of a price
+        if let Some(error) = json["error"].as_str() {
+            return Err(MessageError::no_data(ApiProvider::ParaSwap, error));
+        }
 
-        // get price from json
-        let price: f64 = json["priceRoute"]["srcAmount"].as_str().unwrap().parse()?;
+        // the price is only usable in ETH, so a route priced in anything else is unavailable
+        if let Some(src_token) = json["priceRoute"]["srcToken"]
+            .as_str()
+            .filter(|token| !token.eq_ignore_ascii_case("0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE"))
+        {
+            return Err(MessageError::no_data(
+                ApiProvider::ParaSwap,
+                format!("route not priced in ETH: {}", src_token),
+            ));
+        }
 
-        let price = price / 10f64.powi(18);
+        // get price from json, in wei
+        let price: u128 = json["priceRoute"]["srcAmount"]
+            .as_str()
+            .ok_or_else(|| {
+                MessageError::parse(ApiProvider::ParaSwap, format!("missing srcAmount: {}", json))
+            })?
+            .parse()
+            .map_err(|e| MessageError::parse(ApiProvider::ParaSwap, e))?;
+
+        let price = Decimal::try_from_i128_with_scale(price as i128, 18)
+            .map_err(|e| MessageError::parse(ApiProvider::ParaSwap, e))?
+            .normalize();
+
+        // a zero price would make every token look like free profit
+        if price.is_zero() {
+            return Err(MessageError::no_data(ApiProvider::ParaSwap, "zero price"));
+        }
 
-        println!("price: {}", price);
+        info!("price: {}", price);
 
         Ok(price)
     }
 
-    pub(crate) async fn get_top_bid(&self, collection: &str, token_id: U256) -> TopBid {
-        let client = reqwest::Client::new();
+    pub(crate) async fn get_tx_details(
+        tx_hash: &str,
+        api_key: &str,
+    ) -> Result<TxDetails, MessageError> {
+        let client = crate::http::client();
+
+        // gas used comes from the receipt, the method selector from the transaction input
+        let receipt_url = format! {"https://api.etherscan.io/api?module=proxy&action=eth_getTransactionReceipt&txhash={}&apikey={}", tx_hash, api_key};
+        let receipt = get_json(
+            ApiProvider::Etherscan,
+            client.get(receipt_url).header("accept", "application/json"),
+        )
+        .await?;
+
+        let tx_url = format! {"https://api.etherscan.io/api?module=proxy&action=eth_getTransactionByHash&txhash={}&apikey={}", tx_hash, api_key};
+        let tx = get_json(
+            ApiProvider::Etherscan,
+            client.get(tx_url).header("accept", "application/json"),
+        )
+        .await?;
+
+        let gas_used = receipt["result"]["gasUsed"]
+            .as_str()
+            .ok_or_else(|| {
+                MessageError::parse(ApiProvider::Etherscan, format!("missing gasUsed: {}", receipt))
+            })?;
+        let gas_used = u64::from_str_radix(gas_used.trim_start_matches("0x"), 16)
+            .map_err(|e| MessageError::parse(ApiProvider::Etherscan, e))?;
+
+        let input = tx["result"]["input"]
+            .as_str()
+            .ok_or_else(|| {
+                MessageError::parse(ApiProvider::Etherscan, format!("missing input: {}", tx))
+            })?;
+        // the 4 byte selector is the first 10 chars including the 0x prefix
+        let method_id = input.get(..10).unwrap_or(input).to_string();
+
+        let to = tx["result"]["to"].as_str().unwrap_or_default().to_string();
+
+        Ok(TxDetails {
+            gas_used,
+            method_id,
+            to,
+        })
+    }
+
+    pub(crate) async fn get_collection_stats(
+        collection: &str,
+    ) -> Result<CollectionStats, MessageError> {
+        let client = crate::http::client();
+
+        let url = format! {"https://api.reservoir.tools/collections/v7?id={}", collection};
+
+        let req = client
+            .get(url)
+            .header("accept", "application/json")
+            .header("x-api-key", api_key(ApiProvider::Reservoir, "RESERVOIR_API_KEY")?);
+
+        let json = get_json(ApiProvider::Reservoir, req).await?;
+
+        let stats = &json["collections"][0];
+        if stats.is_null() {
+            return Err(MessageError::parse(
+                ApiProvider::Reservoir,
+                format!("collection not found: {}", json),
+            ));
+        }
+
+        Ok(CollectionStats {
+            volume_1day: stats["volume"]["1day"].as_f64(),
+            listings: stats["onSaleCount"]
+                .as_u64()
+                .or_else(|| stats["onSaleCount"].as_str()?.parse().ok()),
+            floor: stats["floorAsk"]["price"]["amount"]["decimal"].as_f64(),
+        })
+    }
+
+    /// Up to `limit` ids of `collection` tokens held by `owner`, per Reservoir.
+    pub(crate) async fn get_owned_tokens(
+        owner: Address,
+        collection: &str,
+        limit: usize,
+    ) -> Result<Vec<U256>, MessageError> {
+        let client = crate::http::client();
+
+        let url = format!(
+            "https://api.reservoir.tools/users/{:#x}/tokens/v10?collection={}&limit={}",
+            owner, collection, limit
+        );
+
+        let req = client.get(url).header("accept", "application/json").header(
+            "x-api-key",
+            api_key(ApiProvider::Reservoir, "RESERVOIR_API_KEY")?,
+        );
+
+        let json = get_json(ApiProvider::Reservoir, req).await?;
+
+        Ok(json["tokens"]
+            .as_array()
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .filter_map(|token| {
+                        U256::from_dec_str(token["token"]["tokenId"].as_str()?).ok()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Reservoir's view of a single token, including its image and last sale.
+    pub(crate) async fn get_reservoir_token(
+        collection: &str,
+        token_id: U256,
+    ) -> Result<serde_json::Value, MessageError> {
+        let client = crate::http::client();
 
-        let url = format! {"https://api.reservoir.tools/orders/bids/v6?token={}%3A{}&status=active&normalizeRoyalties=true&sortBy=price&limit=1&displayCurrency=0x0000000000000000000000000000000000000000", collection, token_id};
+        let url = format! {"https://api.reservoir.tools/tokens/v7?tokens={}%3A{}&includeLastSale=true", collection, token_id};
 
         let req = client
             .get(url)
             .header("accept", "application/json")
-            .header("x-api-key", dotenv::var("RESERVOIR_API_KEY").unwrap());
+            .header("x-api-key", api_key(ApiProvider::Reservoir, "RESERVOIR_API_KEY")?);
+
+        let mut json = get_json(ApiProvider::Reservoir, req).await?;
 
-        let res = req.send().await.unwrap();
+        match json["tokens"][0]["token"].take() {
+            serde_json::Value::Null => Err(MessageError::no_data(
+                ApiProvider::Reservoir,
+                format!("no token {} in {}", token_id, collection),
+            )),
+            token => Ok(token),
+        }
+    }
+
+    pub(crate) async fn get_top_bid(
+        &self,
+        collection: &str,
+        token_id: U256,
+    ) -> Result<TopBid, MessageError> {
+        let client = crate::http::client();
+
+        // with an allowlist, look past the best bids for one from a trusted source
+        let allowlist = bid_source_allowlist();
+        let limit = if allowlist.is_empty() { 1 } else { 50 };
+
+        let url = format! {"https://api.reservoir.tools/orders/bids/v6?token={}%3A{}&status=active&normalizeRoyalties=true&sortBy=price&limit={}", collection, token_id, limit};
+
+        let req = client
+            .get(url)
+            .header("accept", "application/json")
+            .header("x-api-key", api_key(ApiProvider::Reservoir, "RESERVOIR_API_KEY")?);
 
         // get json from response
+        let json = get_json(ApiProvider::Reservoir, req).await?;
+
+        let orders = json["orders"].as_array().cloned().unwrap_or_default();
+        // a token nobody bid on is normal, not a response we failed to parse
+        if orders.is_empty() {
+            return Err(MessageError::no_data(
+                ApiProvider::Reservoir,
+                "no active bids",
+            ));
+        }
+
+        let excluded = if allowlist.is_empty() {
+            0
+        } else {
+            orders
+                .iter()
+                .position(|order| {
+                    ["name", "domain"].iter().any(|field| {
+                        order["source"][field]
+                            .as_str()
+                            .is_some_and(|source| allowlist.contains(&source.to_lowercase()))
+                    })
+                })
+                .ok_or_else(|| {
+                    MessageError::no_data(
+                        ApiProvider::Reservoir,
+                        format!("none of {} bids are from an allowed source", orders.len()),
+                    )
+                })?
+        };
+        let order = &json["orders"][excluded];
+
+        // `native` is the ETH equivalent, `decimal` is in the currency the bid was made in
+        let net_amount = &order["price"]["netAmount"];
+        let top_bid = parse_decimal(&net_amount["native"])
+            .map_err(|e| MessageError::parse(ApiProvider::Reservoir, e))?;
+
+        let symbol = order["price"]["currency"]["symbol"].as_str().unwrap_or("ETH");
+        let amount = parse_decimal(&net_amount["decimal"]).ok();
+        debug!(
+            "Top bid for {} {}: {:?} {} (raw {}), normalized to {} ETH",
+            collection, token_id, amount, symbol, net_amount["raw"], top_bid
+        );
 
-        let json = res.json::<serde_json::Value>().await.unwrap();
+        // ETH and WETH convert 1:1, so a gap means the conversion to ETH went wrong somewhere
+        let mismatch_pct: f64 = crate::config::var_or("BID_CURRENCY_MISMATCH_PCT", 1.0);
+        let currency_mismatch = mismatch_pct > 0.0
+            && matches!(symbol, "ETH" | "WETH")
+            && amount
+                .and_then(|amount| percent_of(top_bid - amount, amount))
+                .is_some_and(|pct| pct.abs() > mismatch_pct);
+        if currency_mismatch {
+            warn!(
+                "Top bid for {} {} is {:?} {} but {} ETH after conversion",
+                collection, token_id, amount, symbol, top_bid
+            );
+        }
 
-        let top_bid = json["orders"][0]["price"]["netAmount"]["decimal"].to_string();
+        let native = match amount {
+            Some(amount) if symbol != "ETH" && crate::config::var_or("SHOW_BID_CURRENCY", true) => {
+                Some((amount.normalize(), symbol.to_string()))
+            }
+            _ => None,
+        };
 
-        let top_bid_url = json["orders"][0]["source"]["url"].to_string();
+        let top_bid_url = order["source"]["url"].to_string();
 
-        let top_bid_kind = json["orders"][0]["source"]["name"].to_string();
+        let top_bid_kind = order["source"]["name"].to_string();
 
-        TopBid {
+        let mut top_bid = TopBid {
             url: top_bid_url,
             kind: top_bid_kind,
-            price: top_bid.parse::<f64>().unwrap(),
+            scope: BidScope::from_json(&order["criteria"]["kind"]),
+            quantity: order["quantityRemaining"].as_u64(),
+            price: top_bid,
+            currency_mismatch,
+            excluded,
+            native,
+            ..Default::default()
+        };
+        top_bid.fill_probability = crate::config::per_collection::<f64>("FILL_PROBABILITY")
+            .get(&top_bid.source())
+            .copied();
+
+        Ok(top_bid)
+    }
+
+    /// The collection's top bid from Reservoir, which any token of the collection can fill.
+    /// `None` when the collection has no bids.
+    pub(crate) async fn get_floor_bid(collection: &str) -> Result<Option<TopBid>, MessageError> {
+        let client = crate::http::client();
+
+        let url = format!(
+            "https://api.reservoir.tools/collections/v7?id={}&normalizeRoyalties=true",
+            collection
+        );
+
+        let req = client.get(url).header("accept", "application/json").header(
+            "x-api-key",
+            api_key(ApiProvider::Reservoir, "RESERVOIR_API_KEY")?,
+        );
+
+        let json = get_json(ApiProvider::Reservoir, req).await?;
+
+        let bid = &json["collections"][0]["topBid"];
+        if bid["price"].is_null() {
+            return Ok(None);
         }
+
+        let price = parse_decimal(&bid["price"]["netAmount"]["native"])
+            .map_err(|e| MessageError::parse(ApiProvider::Reservoir, e))?;
+        let domain = bid["sourceDomain"].as_str().unwrap_or_default();
+
+        let mut floor_bid = TopBid {
+            url: format!("https://{}", domain),
+            kind: bid["sourceDomain"].to_string(),
+            scope: Some(BidScope::Collection),
+            price,
+            floor_bid: true,
+            ..Default::default()
+        };
+        floor_bid.fill_probability = crate::config::per_collection::<f64>("FILL_PROBABILITY")
+            .get(&floor_bid.source())
+            .copied();
+
+        Ok(Some(floor_bid))
     }
 
+    /// The token's valuation, from DeepNFTValue and/or the custom `VALUATION_ENDPOINT`.
     pub(crate) async fn get_valuation(
         &self,
+        state: &AppState,
+        collection: &str,
+        token_id: U256,
+    ) -> Result<Option<Valuation>, MessageError> {
+        let endpoint = ValuationEndpoint::from_env();
+
+        let source = ValuationSource::for_collection(collection, endpoint.as_ref());
+
+        let valuation = match (source, &endpoint) {
+            (ValuationSource::Floor, _) => get_floor_valuation(collection).await?,
+            (ValuationSource::Endpoint, Some(endpoint)) => {
+                get_endpoint_valuation(endpoint, collection, token_id).await?
+            }
+            // the endpoint is only a fallback for collections priced by DeepNFTValue
+            (_, Some(endpoint)) if endpoint.mode == ValuationEndpointMode::Fallback => {
+                match self.get_deep_valuation(state, collection, token_id).await {
+                    Ok(Some(valuation)) => Some(valuation),
+                    Ok(None) => get_endpoint_valuation(endpoint, collection, token_id).await?,
+                    Err(e) => {
+                        warn!(
+                            "Error getting DeepNFTValue valuation, asking {}: {}",
+                            endpoint.name, e
+                        );
+                        get_endpoint_valuation(endpoint, collection, token_id).await?
+                    }
+                }
+            }
+            _ => self.get_deep_valuation(state, collection, token_id).await?,
+        };
+
+        let Some(mut valuation) = valuation else {
+            return Ok(None);
+        };
+
+        let max_age = dotenv::var("VALUATION_MAX_AGE_HOURS")
+            .ok()
+            .and_then(|hours| hours.parse::<i64>().ok())
+            .map(chrono::Duration::hours);
+
+        valuation.stale = match (valuation.updated_at, max_age) {
+            (Some(updated_at), Some(max_age)) => Utc::now() - updated_at > max_age,
+            _ => false,
+        };
+
+        let drop_stale = matches!(dotenv::var("STALE_VALUATION_POLICY").as_deref(), Ok("drop"));
+        if valuation.stale && drop_stale {
+            info!("Dropping stale valuation for token {}", token_id);
+            return Ok(None);
+        }
+
+        Ok(Some(valuation))
+    }
+
+    async fn get_deep_valuation(
+        &self,
+        state: &AppState,
         collection: &str,
         token_id: U256,
-    ) -> Option<Valuation> {
-        let details = match self.slug(collection).await {
+    ) -> Result<Option<Valuation>, MessageError> {
+        let details = match self.slug(state, collection).await {
             Some(slug) => {
                 // use deepnftvalue api
 
-                let client = reqwest::Client::new();
+                let client = crate::http::client();
 
-                let url = format! {"https://api.deepnftvalue.com/v1/tokens/{}/{}", slug, token_id};
+                let (url, asset_url) = deep_nft_value_urls(&slug, token_id);
 
-                let req = client
-                    .get(url)
-                    .header(
-                        reqwest::header::AUTHORIZATION,
-                        dotenv::var("DEEP_API_KEY").unwrap(),
-                    )
-                    .header("accept", "application/json");
+                // the collection is supported once we have a slug, so a null valuation is usually
+                // just lag, e.g. right after a mint, and worth a re-check or two
+                let retries: u32 = crate::config::var_or("VALUATION_NULL_RETRIES", 0);
+                let mut attempt = 0;
+                let json = loop {
+                    let req = client
+                        .get(&url)
+                        .header(
+                            reqwest::header::AUTHORIZATION,
+                            api_key(ApiProvider::DeepNftValue, "DEEP_API_KEY")?,
+                        )
+                        .header("accept", "application/json");
+
+                    // get json from response
+                    let json = get_json(ApiProvider::DeepNftValue, req).await?;
 
-                let res = req.send().await.unwrap();
+                    if !json["valuation"].is_null() || attempt >= retries {
+                        break json;
+                    }
 
-                // get json from response
-                let json = res.json::<serde_json::Value>().await.unwrap();
+                    attempt += 1;
+                    info!(
+                        "Null valuation for token {}, re-checking ({} of {})",
+                        token_id, attempt, retries
+                    );
+                    let delay = crate::config::var_or("VALUATION_NULL_RETRY_DELAY_MS", 1_000);
+                    tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                };
 
-                // if valuation is None, return after printing error
+                // if valuation is None, return after printing why
                 let valuation = match json["valuation"].as_object() {
                     Some(valuation) => valuation,
                     None => {
-                        println!("Error getting valuation: {:?}", json);
-                        return None;
+                        info!("No valuation for token {}: {:?}", token_id, json);
+                        return Ok(None);
                     }
                 };
 
                 // get valuation.price from json
-                let price = valuation["price"].as_str().unwrap();
-
-                // create link to deepnftvalue
-                let url = format! {"https://deepnftvalue.com/asset/{}/{}", slug, token_id};
-
-                return Some(Valuation {
-                    url,
-                    price: price.parse::<f64>().unwrap(),
-                });
+                let price = valuation["price"]
+                    .as_str()
+                    .ok_or_else(|| {
+                        MessageError::parse(
+                            ApiProvider::DeepNftValue,
+                            format!("missing price: {:?}", valuation),
+                        )
+                    })?
+                    .parse::<Decimal>()
+                    .map_err(|e| MessageError::parse(ApiProvider::DeepNftValue, e))?;
+
+                // DeepNFTValue has used a few names for the appraisal time
+                let updated_at = ["updated_at", "created_at", "timestamp", "date"]
+                    .iter()
+                    .find_map(|key| parse_timestamp(valuation.get(*key)?));
+
+                Some(Valuation {
+                    source: "DeepNFTValue".to_string(),
+                    url: asset_url,
+                    price,
+                    updated_at,
+                    stale: false,
+                })
             }
             None => None,
         };
 
-        details
+        Ok(details)
+    }
+
+    /// Resolve the slugs of `collections` from Reservoir in batches and cache them, so the first
+    /// event for each collection doesn't wait on a lookup. Collections without a slug are logged.
+    pub(crate) async fn resolve_slugs(state: &AppState, collections: &[String]) {
+        let client = crate::http::client();
+
+        // reservoir accepts up to 20 contracts per request
+        for chunk in collections.chunks(20) {
+            let query = chunk
+                .iter()
+                .map(|collection| format!("contract={}", collection))
+                .collect::<Vec<_>>()
+                .join("&");
+            let url = format!("https://api.reservoir.tools/collections/v7?{}&limit=20", query);
+
+            let json = match api_key(ApiProvider::Reservoir, "RESERVOIR_API_KEY") {
+                Ok(key) => {
+                    get_json(
+                        ApiProvider::Reservoir,
+                        client
+                            .get(url)
+                            .header("accept", "application/json")
+                            .header("x-api-key", key),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            let json = match json {
+                Ok(json) => json,
+                Err(e) => {
+                    error!("Error resolving slugs for {}: {}", chunk.join(", "), e);
+                    continue;
+                }
+            };
+
+            let mut slugs = state.collection_slugs.lock().unwrap();
+            for collection in json["collections"].as_array().into_iter().flatten() {
+                if let (Some(id), Some(slug)) =
+                    (collection["id"].as_str(), collection["slug"].as_str())
+                {
+                    info!(
+                        "Resolved {} to {} ({})",
+                        id,
+                        slug,
+                        collection["name"].as_str().unwrap_or("unnamed")
+                    );
+                    slugs.insert(id.to_lowercase(), slug.to_string());
+                }
+            }
+        }
+
+        let slugs = state.collection_slugs.lock().unwrap();
+        for collection in collections {
+            if !slugs.contains_key(collection) {
+                warn!("Could not resolve a slug for {}", collection);
+            }
+        }
     }
 
-    pub(crate) async fn slug(&self, collection: &str) -> Option<String> {
+    /// The collection's slug, from the built in map or else from those resolved at startup.
+    pub(crate) async fn slug(&self, state: &AppState, collection: &str) -> Option<String> {
         // hashmap of collection addresses to slugs
         let collection_slugs: HashMap<String, String> = {
             let mut inner = HashMap::new();
@@ -378,5 +2749,432 @@ impl Message {
         collection_slugs
             .get(collection)
             .map(|slug| slug.to_string())
+            .or_else(|| state.collection_slugs.lock().unwrap().get(collection).cloned())
+    }
+}
+
+async fn get_endpoint_valuation(
+    endpoint: &ValuationEndpoint,
+    collection: &str,
+    token_id: U256,
+) -> Result<Option<Valuation>, MessageError> {
+    Ok(endpoint
+        .valuation(collection, token_id)
+        .await?
+        .map(|valuation| Valuation {
+            source: endpoint.name.clone(),
+            url: valuation.url.unwrap_or_default(),
+            price: valuation.price,
+            updated_at: valuation.updated_at,
+            stale: false,
+        }))
+}
+
+/// The collection's floor ask as a valuation, for collections without a better source.
+async fn get_floor_valuation(collection: &str) -> Result<Option<Valuation>, MessageError> {
+    let stats = Message::get_collection_stats(collection).await?;
+
+    Ok(stats
+        .floor
+        .and_then(Decimal::from_f64)
+        .map(|price| Valuation {
+            source: "Reservoir floor".to_string(),
+            url: String::new(),
+            price,
+            updated_at: None,
+            stale: false,
+        }))
+}
+
+/// Parse a decimal price that an API may serialize as either a JSON number or a string.
+/// A null or missing value is an error rather than a zero price.
+pub(crate) fn parse_decimal(value: &serde_json::Value) -> eyre::Result<Decimal> {
+    match value {
+        serde_json::Value::Number(number) => parse_decimal_str(&number.to_string()),
+        serde_json::Value::String(string) => parse_decimal_str(string.trim()),
+        serde_json::Value::Null => eyre::bail!("decimal is null"),
+        other => eyre::bail!("unexpected decimal value: {}", other),
+    }
+}
+
+/// Parse an RFC 3339 timestamp, a bare `YYYY-MM-DD` date or unix seconds.
+pub(crate) fn parse_timestamp(value: &serde_json::Value) -> Option<DateTime<Utc>> {
+    match value {
+        serde_json::Value::String(string) => DateTime::parse_from_rfc3339(string)
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .ok()
+            .or_else(|| {
+                let date = NaiveDate::parse_from_str(string, "%Y-%m-%d").ok()?;
+                Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+            }),
+        serde_json::Value::Number(number) => Utc.timestamp_opt(number.as_i64()?, 0).single(),
+        _ => None,
+    }
+}
+
+/// Lowercase marketplace names or domains from `BID_SOURCE_ALLOWLIST`, comma separated. Empty
+/// means every source is accepted.
+fn bid_source_allowlist() -> Vec<String> {
+    dotenv::var("BID_SOURCE_ALLOWLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|source| source.trim().to_lowercase())
+        .filter(|source| !source.is_empty())
+        .collect()
+}
+
+/// Keep only the first of each repeated token id, in emitted order, returning how many were
+/// dropped.
+fn drop_duplicates(token_ids: &mut Vec<U256>) -> usize {
+    let emitted = token_ids.len();
+    let mut seen = std::collections::HashSet::new();
+    token_ids.retain(|token_id| seen.insert(*token_id));
+
+    emitted - token_ids.len()
+}
+
+/// DeepNFTValue's API url and web page for a token. Both take the token id in decimal, which
+/// `U256`'s `Display` writes exactly however large the id.
+fn deep_nft_value_urls(slug: &str, token_id: U256) -> (String, String) {
+    (
+        format!(
+            "https://api.deepnftvalue.com/v1/tokens/{}/{}",
+            slug, token_id
+        ),
+        format!("https://deepnftvalue.com/asset/{}/{}", slug, token_id),
+    )
+}
+
+/// Log an upstream error and add its provider and category to `errors` for the metrics.
+fn record_error<T>(
+    errors: &mut Vec<(ApiProvider, ErrorCategory)>,
+    what: &str,
+    e: MessageError,
+) -> Option<T> {
+    // an open breaker was already reported when it opened
+    if matches!(e, MessageError::CircuitOpen { .. }) {
+        debug!("Skipped getting {}: {}", what, e);
+    } else {
+        error!("Error getting {}: {}", what, e);
+    }
+    errors.push((e.provider(), e.category()));
+    None
+}
+
+/// Await building one token, treating a panic like a token that was filtered out, so one token's
+/// bad data can't take the rest of the event down with it.
+async fn isolate_panic<T: Default>(
+    token_id: U256,
+    token: impl std::future::Future<Output = T>,
+) -> T {
+    match AssertUnwindSafe(token).catch_unwind().await {
+        Ok(token) => token,
+        Err(_) => {
+            error!("Panic while processing token {}, skipping it", token_id);
+            T::default()
+        }
+    }
+}
+
+/// Render a duration the way a trader reads it: "3d", "5h" or "12m".
+pub(crate) fn format_age(age: chrono::Duration) -> String {
+    if age.num_days() > 0 {
+        format!("{}d", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h", age.num_hours())
+    } else {
+        format!("{}m", age.num_minutes().max(0))
+    }
+}
+
+/// Exact decimal parsing, accepting exponent notation like `1.5e-3` as well.
+fn parse_decimal_str(string: &str) -> eyre::Result<Decimal> {
+    Decimal::from_str(string)
+        .or_else(|_| Decimal::from_scientific(string))
+        .map_err(|e| eyre::eyre!("invalid decimal {}: {}", string, e))
+}
+
+/// `value` as a percentage of `base`, or `None` when `base` is zero or negative.
+fn percent_of(value: Decimal, base: Decimal) -> Option<f64> {
+    if base > Decimal::ZERO {
+        (value / base * Decimal::ONE_HUNDRED).to_f64()
+    } else {
+        None
+    }
+}
+
+/// Serialize a `U256` as a decimal string. Its own `Serialize` writes hex, which nothing else we
+/// produce uses for token ids.
+fn serialize_decimal<S: serde::Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// " (22%)", or nothing when the percentage is unknown.
+fn format_pct(pct: Option<f64>) -> String {
+    match pct {
+        Some(pct) => format!(" ({:.0}%)", pct),
+        None => String::new(),
+    }
+}
+
+/// A compact one line form of a fragment event and where it was logged.
+pub(crate) fn raw_log(log: &FragmentNftFilter, meta: &LogMeta) -> String {
+    let token_ids = log
+        .token_ids
+        .iter()
+        .map(|token_id| token_id.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(
+        "operator={:#x} onBehalfOf={:#x} collection={:#x} tokenIds=[{}] block={} logIndex={}",
+        log.operator, log.on_behalf_of, log.collection, token_ids, meta.block_number, meta.log_index
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(token_id: u64) -> Token {
+        Token {
+            token_id: U256::from(token_id),
+            ..Token::default()
+        }
+    }
+
+    #[test]
+    fn all_sources_failing_flags_the_token() {
+        let mut token = token(1);
+        token.compute_profit(None, SellPriceBasis::TopBid, &Fees::default());
+
+        assert!(token.apply_no_data_policy(false, NoDataPolicy::Flag));
+        assert!(token.no_data);
+        assert_eq!(token.profit, None);
+
+        let mut message = Message {
+            tokens: vec![token],
+            ..Message::default()
+        };
+        message.compute_total_profit();
+
+        assert_eq!(message.total_profit, Decimal::ZERO);
+        assert!(message
+            .to_string()
+            .contains("No data from any price source — do not trust profit"));
+    }
+
+    #[test]
+    fn all_sources_failing_skips_the_token() {
+        assert!(!token(1).apply_no_data_policy(false, NoDataPolicy::Skip));
+    }
+
+    #[test]
+    fn any_source_counts_as_data() {
+        let mut with_bid = Token {
+            top_bid: Some(TopBid::default()),
+            ..token(1)
+        };
+        assert!(with_bid.apply_no_data_policy(false, NoDataPolicy::Skip));
+        assert!(!with_bid.no_data);
+
+        let mut with_valuation = Token {
+            valuation: Some(Valuation::default()),
+            ..token(2)
+        };
+        assert!(with_valuation.apply_no_data_policy(false, NoDataPolicy::Skip));
+
+        let mut with_mu_token = token(3);
+        assert!(with_mu_token.apply_no_data_policy(true, NoDataPolicy::Skip));
+        assert!(!with_mu_token.no_data);
+    }
+
+    #[tokio::test]
+    async fn failing_tokens_dont_stop_the_rest_of_the_event() {
+        let mut tokens = Vec::new();
+        for token_id in 1..=4u64 {
+            let built = async move {
+                match token_id {
+                    2 => panic!("bad data for token 2"),
+                    // filtered out or failed without panicking
+                    3 => None,
+                    _ => Some(token(token_id)),
+                }
+            };
+            if let Some(token) = isolate_panic(U256::from(token_id), built).await {
+                tokens.push(token);
+            }
+        }
+
+        let token_ids = tokens
+            .iter()
+            .map(|token| token.token_id)
+            .collect::<Vec<_>>();
+        assert_eq!(token_ids, vec![U256::from(1), U256::from(4)]);
+    }
+
+    /// A message for four tokens bought with MuTokens at 1 ETH per NFT and sold to their top bid,
+    /// for profits of 0.5, -0.2, 1.5 and unknown, in that emitted order.
+    fn mixed_profit_message() -> Message {
+        let mu_token = MuToken {
+            derived_price: Decimal::ONE,
+            ..MuToken::default()
+        };
+        let bids = [
+            Some(Decimal::new(15, 1)),
+            Some(Decimal::new(8, 1)),
+            Some(Decimal::new(25, 1)),
+            None,
+        ];
+
+        let tokens = bids
+            .into_iter()
+            .zip(1u64..)
+            .map(|(bid, token_id)| {
+                let mut token = Token {
+                    top_bid: bid.map(|price| TopBid {
+                        price,
+                        ..TopBid::default()
+                    }),
+                    ..token(token_id)
+                };
+                token.compute_profit(Some(&mu_token), SellPriceBasis::TopBid, &Fees::default());
+                token
+            })
+            .collect();
+
+        let mut message = Message {
+            mu_token: Some(mu_token),
+            tokens,
+            ..Message::default()
+        };
+        message.compute_total_profit();
+        message
+    }
+
+    fn rendered_order(message: &Message) -> Vec<u64> {
+        let rendered = message.to_string();
+        let mut positions = (1..=4u64)
+            .filter_map(|token_id| {
+                Some((rendered.find(&format!("Token {}:", token_id))?, token_id))
+            })
+            .collect::<Vec<_>>();
+        positions.sort();
+        positions
+            .into_iter()
+            .map(|(_, token_id)| token_id)
+            .collect()
+    }
+
+    #[test]
+    fn total_profit_sums_only_positive_profits() {
+        let message = mixed_profit_message();
+
+        let profits = message
+            .tokens
+            .iter()
+            .map(|token| token.profit)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            profits,
+            vec![
+                Some(Decimal::new(5, 1)),
+                Some(Decimal::new(-2, 1)),
+                Some(Decimal::new(15, 1)),
+                None
+            ]
+        );
+        assert_eq!(message.total_profit, Decimal::new(20, 1));
+        // 2 ETH on the 2 ETH the two profitable tokens cost
+        assert_eq!(message.total_profit_pct, Some(100f64));
+    }
+
+    #[test]
+    fn tokens_render_in_the_configured_order() {
+        let mut message = mixed_profit_message();
+        assert_eq!(rendered_order(&message), vec![1, 2, 3, 4]);
+
+        TokenSort::ProfitDesc.apply(&mut message.tokens);
+        assert_eq!(rendered_order(&message), vec![3, 1, 2, 4]);
+
+        TokenSort::TokenId.apply(&mut message.tokens);
+        assert_eq!(rendered_order(&message), vec![1, 2, 3, 4]);
+
+        // sorting is presentational, the total stays the same
+        assert_eq!(message.total_profit, Decimal::new(20, 1));
+    }
+
+    #[test]
+    fn compact_highlights_the_most_profitable_token() {
+        let mut message = mixed_profit_message();
+        assert!(message.compact().to_string().contains("best #3 at 1.5 ETH"));
+
+        // whatever order the tokens are listed in
+        TokenSort::TokenId.apply(&mut message.tokens);
+        message.tokens.reverse();
+        assert!(message.compact().to_string().contains("best #3 at 1.5 ETH"));
+    }
+
+    #[test]
+    fn collapsed_tokens_sum_only_positive_profits() {
+        let message = Message {
+            max_detailed: Some(1),
+            ..mixed_profit_message()
+        };
+
+        // #3 is detailed, #1 (+0.5), #2 (-0.2) and #4 (no bid) are collapsed
+        assert!(message
+            .to_string()
+            .contains("and 3 more tokens (total +0.5 ETH)"));
+    }
+
+    #[test]
+    fn near_max_token_ids_are_decimal_everywhere() {
+        let token_id = U256::MAX - 1;
+        let decimal =
+            "115792089237316195423570985008687907853269984665640564039457584007913129639934";
+        let collection = "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d";
+
+        let links = MarketplaceLink::parse(DEFAULT_MARKETPLACE_LINKS);
+        assert_eq!(links.len(), 3);
+        for link in &links {
+            let url = link.url(collection, token_id);
+            assert!(
+                url.ends_with(&format!("{}/{}", collection, decimal)),
+                "{}",
+                url
+            );
+        }
+
+        let (api_url, asset_url) = deep_nft_value_urls("bayc", token_id);
+        assert_eq!(
+            api_url,
+            format!("https://api.deepnftvalue.com/v1/tokens/bayc/{}", decimal)
+        );
+        assert_eq!(
+            asset_url,
+            format!("https://deepnftvalue.com/asset/bayc/{}", decimal)
+        );
+
+        let token = serde_json::to_value(token(0)).unwrap();
+        assert_eq!(token["token_id"], "0");
+        let token = serde_json::to_value(Token {
+            token_id,
+            ..Token::default()
+        })
+        .unwrap();
+        assert_eq!(token["token_id"], decimal);
+    }
+
+    #[test]
+    fn duplicate_token_ids_are_processed_once() {
+        let mut token_ids = [7u64, 3, 7, 9, 3, 7].map(U256::from).to_vec();
+
+        assert_eq!(drop_duplicates(&mut token_ids), 3);
+        assert_eq!(token_ids, [7u64, 3, 9].map(U256::from).to_vec());
+
+        // nothing to drop the second time round
+        assert_eq!(drop_duplicates(&mut token_ids), 0);
+        assert_eq!(token_ids.len(), 3);
     }
 }
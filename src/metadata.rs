@@ -0,0 +1,123 @@
+use base64::Engine;
+use ethers::types::{H160, U256};
+use tracing::error;
+
+use crate::error::{get_json, ApiProvider, MessageError};
+use crate::state::AppState;
+
+/// Gateways tried in order for `ipfs://` uris when `IPFS_GATEWAYS` is unset.
+const DEFAULT_IPFS_GATEWAYS: &str = "https://ipfs.io/ipfs/,https://cloudflare-ipfs.com/ipfs/";
+
+/// What a token's metadata says about it, from its on-chain `tokenURI`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TokenMetadata {
+    pub(crate) name: Option<String>,
+    pub(crate) image: Option<String>,
+}
+
+/// Resolve a token's name and image from `tokenURI(tokenId)`, for collections Reservoir has no
+/// metadata for. Handles `data:` uris (base64 or plain JSON), `ipfs://` uris through each
+/// configured gateway in turn, and plain http(s) uris. Results are cached per token.
+pub(crate) async fn resolve(
+    state: &AppState,
+    collection: &str,
+    token_id: U256,
+) -> Result<TokenMetadata, MessageError> {
+    let key = (collection.to_string(), token_id);
+    if let Some(metadata) = state.token_metadata.get(&key) {
+        return Ok(metadata);
+    }
+
+    let address = collection
+        .parse::<H160>()
+        .map_err(|e| MessageError::parse(ApiProvider::Rpc, e))?;
+    let uri = crate::Erc721::new(address, state.flooring.client())
+        .token_uri(token_id)
+        .await
+        .map_err(MessageError::rpc)?;
+
+    let json = fetch_json(&uri).await?;
+
+    let metadata = TokenMetadata {
+        name: json["name"].as_str().map(|name| name.to_string()),
+        image: json["image"]
+            .as_str()
+            .or_else(|| json["image_url"].as_str())
+            .map(|image| match image.strip_prefix("ipfs://") {
+                // link the first gateway, the reader's client fetches it
+                Some(path) => format!("{}{}", ipfs_gateways()[0], path),
+                None => image.to_string(),
+            }),
+    };
+    state.token_metadata.insert(key, metadata.clone());
+
+    Ok(metadata)
+}
+
+async fn fetch_json(uri: &str) -> Result<serde_json::Value, MessageError> {
+    let client = crate::http::client();
+
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (kind, body) = data.split_once(',').ok_or_else(|| {
+            MessageError::parse(ApiProvider::TokenUri, "data uri without a payload")
+        })?;
+
+        let body = if kind.ends_with(";base64") {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(body)
+                .map_err(|e| MessageError::parse(ApiProvider::TokenUri, e))?;
+            String::from_utf8(bytes).map_err(|e| MessageError::parse(ApiProvider::TokenUri, e))?
+        } else {
+            body.to_string()
+        };
+
+        return serde_json::from_str(&body)
+            .map_err(|e| MessageError::parse(ApiProvider::TokenUri, e));
+    }
+
+    if let Some(path) = uri.strip_prefix("ipfs://") {
+        let path = path.trim_start_matches("ipfs/");
+        let mut last_error = None;
+
+        // public gateways are flaky, so fall through to the next one on any failure
+        for gateway in ipfs_gateways() {
+            match get_json(
+                ApiProvider::TokenUri,
+                client.get(format!("{gateway}{path}")),
+            )
+            .await
+            {
+                Ok(json) => return Ok(json),
+                Err(e) => {
+                    error!("Error fetching {} through {}: {}", uri, gateway, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        return Err(last_error.unwrap_or_else(|| {
+            MessageError::no_data(ApiProvider::TokenUri, "no IPFS gateways configured")
+        }));
+    }
+
+    get_json(ApiProvider::TokenUri, client.get(uri)).await
+}
+
+/// Gateway url prefixes from `IPFS_GATEWAYS`, comma separated.
+fn ipfs_gateways() -> Vec<String> {
+    let gateways = dotenv::var("IPFS_GATEWAYS")
+        .unwrap_or_else(|_| DEFAULT_IPFS_GATEWAYS.to_string())
+        .split(',')
+        .map(|gateway| gateway.trim().to_string())
+        .filter(|gateway| !gateway.is_empty())
+        .collect::<Vec<_>>();
+
+    if gateways.is_empty() {
+        DEFAULT_IPFS_GATEWAYS
+            .split(',')
+            .map(|gateway| gateway.to_string())
+            .collect()
+    } else {
+        gateways
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::breaker::BreakerState;
+use crate::config;
+use crate::error::{ApiProvider, ErrorCategory};
+use crate::ledger::Ledger;
+use crate::state::AppState;
+
+/// Process-wide counters, exported in the Prometheus text format.
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    pub(crate) events_processed: AtomicU64,
+    pub(crate) alerts_sent: AtomicU64,
+    /// events older than `MAX_EVENT_AGE_SECS`, dropped without building a message
+    pub(crate) stale_events_dropped: AtomicU64,
+    /// events being processed right now, at most `MAX_CONCURRENT_EVENTS`
+    pub(crate) events_in_flight: AtomicU64,
+    api_errors: Mutex<BTreeMap<(ApiProvider, ErrorCategory), u64>>,
+    /// `total_profit` of every processed event, sent or not
+    profit: Mutex<Histogram>,
+}
+
+/// A Prometheus histogram. `counts[i]` counts observations up to `buckets[i]`, not cumulative,
+/// and the last count is for everything above the largest bucket.
+#[derive(Debug)]
+struct Histogram {
+    buckets: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(mut buckets: Vec<f64>) -> Self {
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+
+        Histogram {
+            counts: vec![0; buckets.len() + 1],
+            buckets,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = self.buckets.partition_point(|bound| *bound < value);
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let _ = writeln!(out, "# TYPE {name} histogram");
+
+        let mut cumulative = 0;
+        for (bound, count) in self.buckets.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        cumulative += self.counts[self.buckets.len()];
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {cumulative}");
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {cumulative}");
+    }
+}
+
+impl Metrics {
+    /// `profit_buckets` are the histogram's upper bounds in ETH, from `PROFIT_BUCKETS`.
+    pub(crate) fn new(profit_buckets: Vec<f64>) -> Self {
+        Metrics {
+            events_processed: AtomicU64::default(),
+            alerts_sent: AtomicU64::default(),
+            stale_events_dropped: AtomicU64::default(),
+            events_in_flight: AtomicU64::default(),
+            api_errors: Mutex::default(),
+            profit: Mutex::new(Histogram::new(profit_buckets)),
+        }
+    }
+
+    /// Comma separated ETH bounds from `PROFIT_BUCKETS`, or a default spread from dust to 5 ETH.
+    pub(crate) fn profit_buckets() -> Vec<f64> {
+        match dotenv::var("PROFIT_BUCKETS") {
+            Ok(buckets) => buckets
+                .split(',')
+                .filter(|bucket| !bucket.trim().is_empty())
+                .filter_map(|bucket| {
+                    bucket
+                        .trim()
+                        .parse()
+                        .map_err(|_| warn!("Ignoring malformed PROFIT_BUCKETS entry: {bucket}"))
+                        .ok()
+                })
+                .collect(),
+            Err(_) => vec![0.0, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0],
+        }
+    }
+
+    pub(crate) fn observe_profit(&self, profit: Decimal) {
+        if let Some(profit) = profit.to_f64() {
+            self.profit.lock().unwrap().observe(profit);
+        }
+    }
+
+    pub(crate) fn record_errors(&self, errors: &[(ApiProvider, ErrorCategory)]) {
+        let mut api_errors = self.api_errors.lock().unwrap();
+
+        for error in errors {
+            *api_errors.entry(*error).or_default() += 1;
+        }
+    }
+
+    /// One line per provider and category with its error count, for the shutdown summary.
+    pub(crate) fn error_summary(&self) -> String {
+        let api_errors = self.api_errors.lock().unwrap();
+
+        if api_errors.is_empty() {
+            return "No API errors".to_string();
+        }
+
+        api_errors
+            .iter()
+            .map(|((provider, category), count)| format!("{provider} {category}: {count}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub(crate) fn render(&self, ledger: &Ledger) -> String {
+        let ledger = ledger.snapshot();
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# TYPE floor_monitor_events_processed_total counter\nfloor_monitor_events_processed_total {}",
+            self.events_processed.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE floor_monitor_alerts_sent_total counter\nfloor_monitor_alerts_sent_total {}",
+            self.alerts_sent.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE floor_monitor_stale_events_dropped_total counter\nfloor_monitor_stale_events_dropped_total {}",
+            self.stale_events_dropped.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE floor_monitor_events_in_flight gauge\nfloor_monitor_events_in_flight {}",
+            self.events_in_flight.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE floor_monitor_ledger_profit_eth gauge\nfloor_monitor_ledger_profit_eth {}",
+            ledger.total_profit
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE floor_monitor_ledger_alerts gauge\nfloor_monitor_ledger_alerts {}",
+            ledger.alerts
+        );
+
+        self.profit
+            .lock()
+            .unwrap()
+            .render(&mut out, "floor_monitor_profit_eth");
+
+        let _ = writeln!(out, "# TYPE floor_monitor_api_errors_total counter");
+        for ((provider, category), count) in self.api_errors.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "floor_monitor_api_errors_total{{provider=\"{provider}\",category=\"{category}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP floor_monitor_circuit_state 0 closed, 1 half open, 2 open\n# TYPE floor_monitor_circuit_state gauge"
+        );
+        for (provider, state) in crate::breaker::breakers().states() {
+            let value = match state {
+                BreakerState::Closed => 0,
+                BreakerState::HalfOpen => 1,
+                BreakerState::Open => 2,
+            };
+            let _ = writeln!(
+                out,
+                "floor_monitor_circuit_state{{provider=\"{provider}\"}} {value}"
+            );
+        }
+
+        out
+    }
+}
+
+/// Serve the metrics on `addr`. Every request gets the metrics, whatever the path, except
+/// `/events` when `ENABLE_SSE` is set, which streams every alert as a server-sent event.
+pub(crate) async fn serve(addr: String, state: Arc<AppState>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    info!("Serving metrics on {}", addr);
+
+    let sse = config::var_or("ENABLE_SSE", false);
+
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            // drain the request before answering
+            let mut buf = [0u8; 1024];
+            let read = socket.read(&mut buf).await.unwrap_or(0);
+
+            if sse && String::from_utf8_lossy(&buf[..read]).starts_with("GET /events ") {
+                stream_events(socket, state.opportunities.subscribe()).await;
+                return;
+            }
+
+            let body = state.metrics.render(&state.ledger);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Write each serialized alert to `socket` as an SSE `data:` line until the client goes away.
+async fn stream_events(mut socket: TcpStream, mut opportunities: broadcast::Receiver<String>) {
+    let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n";
+    if socket.write_all(headers.as_bytes()).await.is_err() {
+        return;
+    }
+
+    loop {
+        let event = match opportunities.recv().await {
+            Ok(json) => format!("data: {}\n\n", json),
+            // a slow client misses alerts rather than holding the others back
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                format!(": {} alerts dropped, client too slow\n\n", missed)
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        if socket.write_all(event.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
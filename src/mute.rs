@@ -0,0 +1,65 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use tracing::error;
+
+/// Collections silenced at runtime via `/mute`, persisted so they stay muted across restarts.
+pub(crate) struct MuteList {
+    path: PathBuf,
+    muted: Mutex<BTreeSet<String>>,
+}
+
+impl MuteList {
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let muted = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str::<Vec<String>>(&contents)
+                .map(|muted| muted.into_iter().collect())
+                .unwrap_or_else(|e| {
+                    error!("Error reading mute file: {:?}", e);
+                    BTreeSet::new()
+                }),
+            Err(_) => BTreeSet::new(),
+        };
+
+        MuteList {
+            path,
+            muted: Mutex::new(muted),
+        }
+    }
+
+    pub(crate) fn is_muted(&self, collection: &str) -> bool {
+        self.muted
+            .lock()
+            .unwrap()
+            .contains(&collection.to_lowercase())
+    }
+
+    /// Returns false if the collection was already muted.
+    pub(crate) fn mute(&self, collection: &str) -> bool {
+        let mut muted = self.muted.lock().unwrap();
+        let added = muted.insert(collection.to_lowercase());
+        self.persist(&muted);
+        added
+    }
+
+    /// Returns false if the collection wasn't muted.
+    pub(crate) fn unmute(&self, collection: &str) -> bool {
+        let mut muted = self.muted.lock().unwrap();
+        let removed = muted.remove(&collection.to_lowercase());
+        self.persist(&muted);
+        removed
+    }
+
+    pub(crate) fn list(&self) -> Vec<String> {
+        self.muted.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn persist(&self, muted: &BTreeSet<String>) {
+        let json = serde_json::json!(muted.iter().collect::<Vec<_>>());
+
+        if let Err(e) = std::fs::write(&self.path, json.to_string()) {
+            error!("Error writing mute file: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,513 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use teloxide::adaptors::DefaultParseMode;
+use teloxide::prelude::*;
+use teloxide::types::{ChatMemberKind, MessageId, Restricted};
+use tracing::{debug, error, info, warn};
+
+use crate::config;
+use crate::fatal::Fatal;
+use crate::message::Message;
+use crate::sent_alerts::SentAlerts;
+
+/// An output channel alerts are sent to.
+#[async_trait]
+pub(crate) trait Notifier: Send + Sync {
+    /// Short name for logs.
+    fn name(&self) -> &'static str;
+
+    /// Send one delivery, which is a single alert or a batch of them.
+    async fn notify(&self, msgs: &[Message]) -> eyre::Result<()>;
+
+    /// Send a plain notice that isn't an alert, such as a completed cycle.
+    async fn notify_text(&self, text: &str) -> eyre::Result<()>;
+
+    /// Send a plain notice nobody needs to be woken up for, such as a heartbeat.
+    async fn notify_low_priority(&self, text: &str) -> eyre::Result<()> {
+        self.notify_text(text).await
+    }
+
+    /// Check at startup that alerts can be delivered at all, e.g. that the bot may post.
+    async fn check_access(&self) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// Whether this notifier wants `msg` at all.
+    fn accepts(&self, _msg: &Message) -> bool {
+        true
+    }
+}
+
+/// Build every notifier that is configured. Telegram is always on, the rest are opt in.
+pub(crate) fn from_env(sent_alerts: Arc<SentAlerts>) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(Telegram {
+        bot: Bot::new(dotenv::var("TELEGRAM_BOT_TOKEN").unwrap()),
+        chat: "@flooring_monitor".to_string(),
+        thread_id: config::var_opt("TELEGRAM_THREAD_ID"),
+        sent_alerts,
+    })];
+
+    if let Ok(webhook_url) = dotenv::var("DISCORD_WEBHOOK_URL") {
+        notifiers.push(Box::new(Discord { webhook_url }));
+    }
+
+    #[cfg(feature = "nats")]
+    if let Ok(url) = dotenv::var("NATS_URL") {
+        notifiers.push(Box::new(Nats::new(url)));
+    }
+
+    notifiers
+        .into_iter()
+        .map(|notifier| {
+            let filter = Filter::from_env(notifier.name());
+            if filter.is_empty() {
+                notifier
+            } else {
+                info!("Filtering alerts for {}: {:?}", notifier.name(), filter);
+                Box::new(Filtered { notifier, filter })
+            }
+        })
+        .collect()
+}
+
+/// Send `msgs` to every notifier concurrently, logging how each one did. Each notifier only gets
+/// the alerts it accepts. Returns true if at least one of them delivered.
+pub(crate) async fn notify_all(notifiers: &[Box<dyn Notifier>], msgs: &[Message]) -> bool {
+    let results = futures::future::join_all(notifiers.iter().map(|notifier| async move {
+        let accepted = msgs
+            .iter()
+            .filter(|msg| notifier.accepts(msg))
+            .cloned()
+            .collect::<Vec<_>>();
+        if accepted.is_empty() {
+            debug!("No alerts for {} after its filter", notifier.name());
+            return None;
+        }
+
+        Some(notifier.notify(&accepted).await)
+    }))
+    .await;
+
+    log_results(notifiers, results)
+}
+
+/// Like `notify_all`, for a plain notice. Notices aren't filtered.
+pub(crate) async fn notify_all_text(notifiers: &[Box<dyn Notifier>], text: &str) -> bool {
+    let results = futures::future::join_all(
+        notifiers
+            .iter()
+            .map(|notifier| async move { Some(notifier.notify_text(text).await) }),
+    )
+    .await;
+
+    log_results(notifiers, results)
+}
+
+/// Send a low priority notice to the notifiers in `names`, or to all of them when it's empty.
+pub(crate) async fn notify_low_priority(
+    notifiers: &[Box<dyn Notifier>],
+    names: &[String],
+    text: &str,
+) -> bool {
+    let results = futures::future::join_all(notifiers.iter().map(|notifier| async move {
+        if !names.is_empty()
+            && !names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(notifier.name()))
+        {
+            return None;
+        }
+
+        Some(notifier.notify_low_priority(text).await)
+    }))
+    .await;
+
+    log_results(notifiers, results)
+}
+
+/// Run every notifier's startup check. Per `ACCESS_CHECK`, a failure is fatal (`fail`, the
+/// default) or is only logged (`warn`), and `off` skips the checks.
+pub(crate) async fn check_access(notifiers: &[Box<dyn Notifier>]) -> Result<(), Fatal> {
+    let policy = dotenv::var("ACCESS_CHECK").unwrap_or_else(|_| "fail".to_string());
+    if policy == "off" {
+        return Ok(());
+    }
+
+    for notifier in notifiers {
+        let Err(e) = notifier.check_access().await else {
+            continue;
+        };
+
+        if policy == "warn" {
+            warn!("{} can't deliver alerts: {:?}", notifier.name(), e);
+        } else {
+            return Err(Fatal::Notifier(format!(
+                "{} ({:?}), set ACCESS_CHECK=warn to start anyway",
+                notifier.name(),
+                e
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// `None` is a notifier that had nothing to send.
+fn log_results(notifiers: &[Box<dyn Notifier>], results: Vec<Option<eyre::Result<()>>>) -> bool {
+    let mut delivered = false;
+    for (notifier, result) in notifiers.iter().zip(results) {
+        match result {
+            Some(Ok(())) => {
+                info!("Sent to {}", notifier.name());
+                delivered = true;
+            }
+            Some(Err(e)) => error!("Error sending to {}: {:?}", notifier.name(), e),
+            None => {}
+        }
+    }
+
+    delivered
+}
+
+/// Which alerts a notifier takes, from `{NAME}_MIN_PROFIT` and `{NAME}_COLLECTIONS` (comma
+/// separated) with the notifier's name upper cased, e.g. `DISCORD_MIN_PROFIT=1`.
+#[derive(Debug)]
+struct Filter {
+    min_profit: Option<Decimal>,
+    collections: Vec<String>,
+}
+
+impl Filter {
+    fn from_env(name: &str) -> Self {
+        let prefix = name.to_uppercase();
+
+        Filter {
+            min_profit: config::var_opt(&format!("{prefix}_MIN_PROFIT")),
+            collections: config::collections(&format!("{prefix}_COLLECTIONS")),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min_profit.is_none() && self.collections.is_empty()
+    }
+
+    fn accepts(&self, msg: &Message) -> bool {
+        self.min_profit
+            .is_none_or(|min_profit| msg.total_profit >= min_profit)
+            && (self.collections.is_empty()
+                || self.collections.contains(&msg.collection().to_lowercase()))
+    }
+}
+
+/// A notifier with its own `Filter`.
+struct Filtered {
+    notifier: Box<dyn Notifier>,
+    filter: Filter,
+}
+
+#[async_trait]
+impl Notifier for Filtered {
+    fn name(&self) -> &'static str {
+        self.notifier.name()
+    }
+
+    async fn notify(&self, msgs: &[Message]) -> eyre::Result<()> {
+        self.notifier.notify(msgs).await
+    }
+
+    async fn notify_text(&self, text: &str) -> eyre::Result<()> {
+        self.notifier.notify_text(text).await
+    }
+
+    async fn notify_low_priority(&self, text: &str) -> eyre::Result<()> {
+        self.notifier.notify_low_priority(text).await
+    }
+
+    async fn check_access(&self) -> eyre::Result<()> {
+        self.notifier.check_access().await
+    }
+
+    fn accepts(&self, msg: &Message) -> bool {
+        self.filter.accepts(msg) && self.notifier.accepts(msg)
+    }
+}
+
+struct Telegram {
+    bot: Bot,
+    chat: String,
+    /// forum topic to post into, from `TELEGRAM_THREAD_ID`, instead of the general thread
+    thread_id: Option<i32>,
+    /// single alerts are remembered so a later alert for the same token can edit them
+    sent_alerts: Arc<SentAlerts>,
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    /// Batched alerts go out as one message. A single alert improving on a recent one edits that
+    /// message instead, falling back to a new message if the edit fails.
+    async fn notify(&self, msgs: &[Message]) -> eyre::Result<()> {
+        if let [msg] = msgs {
+            if let Some(message_id) = self.sent_alerts.improved_on(msg) {
+                match self.edit_message(message_id, msg.render()).send().await {
+                    Ok(_) => {
+                        info!("Message edited");
+                        self.sent_alerts.record(msg, message_id);
+                        return Ok(());
+                    }
+                    Err(e) => error!("Error editing message, sending a new one: {:?}", e),
+                }
+            }
+        }
+
+        // compact alerts are already one line each
+        let separator = if config::var_or("COMPACT_MODE", false) {
+            "\n"
+        } else {
+            "\n——————\n\n"
+        };
+        let text = msgs
+            .iter()
+            .map(|msg| msg.render())
+            .collect::<Vec<_>>()
+            .join(separator);
+
+        let sent = self.send(&text, false).await?;
+        // a combined message can't be edited on behalf of just one of its alerts
+        if let [msg] = msgs {
+            self.sent_alerts.record(msg, sent.id);
+        }
+
+        Ok(())
+    }
+
+    async fn notify_text(&self, text: &str) -> eyre::Result<()> {
+        self.send(text, false).await?;
+
+        Ok(())
+    }
+
+    /// Sent silently, so the chat gets no sound or alert for it.
+    async fn notify_low_priority(&self, text: &str) -> eyre::Result<()> {
+        self.send(text, true).await?;
+
+        Ok(())
+    }
+
+    /// The bot has to be an admin allowed to post in a channel, or a member allowed to send
+    /// messages in a group.
+    async fn check_access(&self) -> eyre::Result<()> {
+        let me = self.bot.get_me().await?;
+        let chat = self.bot.get_chat(self.chat.clone()).await?;
+        let member = self.bot.get_chat_member(self.chat.clone(), me.id).await?;
+
+        let allowed = if chat.is_channel() {
+            member.can_post_messages()
+        } else {
+            match &member.kind {
+                ChatMemberKind::Restricted(Restricted {
+                    can_send_messages, ..
+                }) => *can_send_messages,
+                ChatMemberKind::Left | ChatMemberKind::Banned(_) => false,
+                ChatMemberKind::Owner(_)
+                | ChatMemberKind::Administrator(_)
+                | ChatMemberKind::Member => true,
+            }
+        };
+
+        if !allowed {
+            eyre::bail!(
+                "@{} is not allowed to post in {}, make it an admin with permission to post",
+                me.username(),
+                self.chat
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Telegram {
+    fn bot(&self) -> DefaultParseMode<Bot> {
+        self.bot
+            .clone()
+            .parse_mode(teloxide::types::ParseMode::Html)
+    }
+
+    /// An html message to the alert chat, in the configured topic if there is one.
+    fn send_message(
+        &self,
+        text: impl Into<String>,
+    ) -> <DefaultParseMode<Bot> as Requester>::SendMessage {
+        let bot = self.bot();
+
+        let request = bot.send_message(self.chat.clone(), text);
+        match self.thread_id {
+            Some(thread_id) => request.message_thread_id(thread_id),
+            None => request,
+        }
+    }
+
+    /// Send `text`, waiting as long as Telegram asks when rate limited, up to
+    /// `TELEGRAM_RATE_LIMIT_SLEEP_SECS`, and trying once more.
+    async fn send(
+        &self,
+        text: &str,
+        silent: bool,
+    ) -> Result<teloxide::types::Message, teloxide::RequestError> {
+        let request = self.send_message(text).disable_notification(silent);
+
+        match request.clone().send().await {
+            Err(teloxide::RequestError::RetryAfter(retry_after)) => {
+                let max_sleep =
+                    Duration::from_secs(config::var_or("TELEGRAM_RATE_LIMIT_SLEEP_SECS", 35));
+                let sleep = retry_after.min(max_sleep);
+                warn!("Rate limited by telegram, retrying in {:?}", sleep);
+                tokio::time::sleep(sleep).await;
+
+                request.send().await
+            }
+            result => result,
+        }
+    }
+
+    /// Replace the text of an alert already sent to the alert chat.
+    fn edit_message(
+        &self,
+        message_id: MessageId,
+        text: impl Into<String>,
+    ) -> <DefaultParseMode<Bot> as Requester>::EditMessageText {
+        self.bot()
+            .edit_message_text(self.chat.clone(), message_id, text)
+    }
+}
+
+struct Discord {
+    webhook_url: String,
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    /// Embeds are limited per message, so each alert gets its own.
+    async fn notify(&self, msgs: &[Message]) -> eyre::Result<()> {
+        for msg in msgs {
+            crate::discord::send(&self.webhook_url, msg).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn notify_text(&self, text: &str) -> eyre::Result<()> {
+        crate::discord::send_text(&self.webhook_url, text).await
+    }
+}
+
+/// Publishes every alert as its `Message::to_json` to `NATS_SUBJECT` (default
+/// `floor_monitor.alerts`), and plain notices as `{"text": ...}` to `{NATS_SUBJECT}.notices`, for
+/// other programs to consume.
+///
+/// Payloads that couldn't be published are kept, up to `NATS_BUFFER` (default 1000) with the
+/// oldest dropped first, and go out ahead of the next delivery, so a broker outage delays alerts
+/// rather than losing them. The client reconnects by itself.
+#[cfg(feature = "nats")]
+struct Nats {
+    url: String,
+    subject: String,
+    connection: tokio::sync::OnceCell<async_nats::Client>,
+    /// `(subject, payload)` not yet published, oldest first
+    pending: std::sync::Mutex<std::collections::VecDeque<(String, Vec<u8>)>>,
+    buffer: usize,
+}
+
+#[cfg(feature = "nats")]
+impl Nats {
+    fn new(url: String) -> Self {
+        Nats {
+            url,
+            subject: dotenv::var("NATS_SUBJECT")
+                .unwrap_or_else(|_| "floor_monitor.alerts".to_string()),
+            connection: tokio::sync::OnceCell::new(),
+            pending: std::sync::Mutex::new(std::collections::VecDeque::new()),
+            buffer: config::var_or("NATS_BUFFER", 1_000),
+        }
+    }
+
+    async fn client(&self) -> eyre::Result<&async_nats::Client> {
+        Ok(self
+            .connection
+            .get_or_try_init(|| async_nats::connect(self.url.as_str()))
+            .await?)
+    }
+
+    /// Queue a payload, then publish everything queued, in order.
+    async fn publish(&self, subject: String, payload: Vec<u8>) -> eyre::Result<()> {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push_back((subject, payload));
+            while pending.len() > self.buffer {
+                pending.pop_front();
+                warn!("NATS buffer full, dropped the oldest unpublished alert");
+            }
+        }
+
+        let client = self.client().await?;
+        loop {
+            let next = self.pending.lock().unwrap().pop_front();
+            let Some((subject, payload)) = next else {
+                break;
+            };
+
+            if let Err(e) = client
+                .publish(subject.clone(), payload.clone().into())
+                .await
+            {
+                self.pending.lock().unwrap().push_front((subject, payload));
+                return Err(e.into());
+            }
+        }
+
+        client.flush().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nats")]
+#[async_trait]
+impl Notifier for Nats {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn notify(&self, msgs: &[Message]) -> eyre::Result<()> {
+        for msg in msgs {
+            self.publish(self.subject.clone(), msg.to_json()?.into_bytes())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn notify_text(&self, text: &str) -> eyre::Result<()> {
+        let payload = serde_json::to_vec(&serde_json::json!({ "text": text }))?;
+
+        self.publish(format!("{}.notices", self.subject), payload)
+            .await
+    }
+
+    async fn check_access(&self) -> eyre::Result<()> {
+        self.client().await?.flush().await?;
+
+        Ok(())
+    }
+}
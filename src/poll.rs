@@ -0,0 +1,368 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::{
+    contract::Contract,
+    core::types::{Address, ValueOrArray, H256},
+    prelude::LogMeta,
+    providers::{Http, Middleware, Provider},
+};
+use rand::Rng;
+use tokio::sync::mpsc::Sender;
+use tracing::{error, warn};
+
+use crate::{config, FragmentNftFilter};
+
+/// Poll for `FragmentNft` events over HTTP, for providers without a usable websocket.
+///
+/// Blocks are queried in inclusive `[from, to]` chunks and `last_processed` only advances once a
+/// chunk has been fetched successfully, so a failed or empty poll never skips or repeats a block.
+/// Only a successful chunk is followed straight away by the next one. After a failure the wait
+/// doubles with every further failure, up to `POLL_MAX_BACKOFF_MS`.
+pub(crate) async fn run(
+    client: Arc<Provider<Http>>,
+    flooring: Vec<Address>,
+    collection_topics: Option<Vec<H256>>,
+    starting_block: Option<u64>,
+    events: Sender<(FragmentNftFilter, LogMeta)>,
+) -> eyre::Result<()> {
+    let interval = Duration::from_millis(config::var_or("POLL_INTERVAL_MS", 12_000));
+    let jitter_ms: u64 = config::var_or("POLL_JITTER_MS", 0);
+    let chunk_size: u64 = config::var_or("POLL_CHUNK_BLOCKS", 1_000);
+    // the most logs the provider returns for one query, 0 if it never truncates
+    let log_cap: usize = config::var_or("POLL_LOG_CAP", 0);
+    let max_backoff =
+        Duration::from_millis(config::var_or("POLL_MAX_BACKOFF_MS", 300_000)).max(interval);
+    // consecutive failed polls, so a struggling or rate limiting RPC isn't hammered
+    let mut failures = 0;
+
+    let mut last_processed = match starting_block {
+        Some(block) => block - 1,
+        None => client.get_block_number().await?.as_u64(),
+    };
+
+    loop {
+        let head = match client.get_block_number().await {
+            Ok(head) => head.as_u64(),
+            Err(e) => {
+                error!("Error getting block number: {:?}", e);
+                failures += 1;
+                sleep(backoff(interval, max_backoff, failures), jitter_ms).await;
+                continue;
+            }
+        };
+
+        // nothing new since the last poll
+        if head <= last_processed {
+            sleep(interval, jitter_ms).await;
+            continue;
+        }
+
+        let from = last_processed + 1;
+        let to = head.min(from + chunk_size - 1);
+
+        match query_complete(
+            &client,
+            &flooring,
+            collection_topics.as_deref(),
+            from,
+            to,
+            log_cap,
+        )
+        .await
+        {
+            Ok(logs) => {
+                for (log, meta) in logs {
+                    if events.send((log, meta)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                last_processed = to;
+                failures = 0;
+            }
+            Err(e) if is_pruned(&e) => {
+                let block = fall_back(&client, &flooring, from, e).await?;
+                // the head couldn't be read either, so nothing moved
+                if block == from {
+                    failures += 1;
+                    sleep(backoff(interval, max_backoff, failures), jitter_ms).await;
+                    continue;
+                }
+                last_processed = block - 1;
+            }
+            Err(e) => {
+                error!("Error querying blocks {} to {}: {:?}", from, to, e);
+                failures += 1;
+                sleep(backoff(interval, max_backoff, failures), jitter_ms).await;
+                continue;
+            }
+        }
+
+        // keep going without waiting while we are catching up
+        if last_processed < head {
+            continue;
+        }
+
+        sleep(interval, jitter_ms).await;
+    }
+}
+
+/// What to do when the node no longer serves the blocks we resume from, from
+/// `PRUNED_BLOCK_POLICY`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PrunedBlockPolicy {
+    /// resume from the oldest block the node still serves
+    Oldest,
+    /// resume from the chain head
+    Latest,
+    /// stop the event source with the node's error
+    Fail,
+}
+
+impl PrunedBlockPolicy {
+    fn from_env() -> Self {
+        match dotenv::var("PRUNED_BLOCK_POLICY").as_deref() {
+            Ok("latest") => PrunedBlockPolicy::Latest,
+            Ok("fail") => PrunedBlockPolicy::Fail,
+            Ok("oldest") | Err(_) => PrunedBlockPolicy::Oldest,
+            Ok(other) => {
+                warn!("Unknown PRUNED_BLOCK_POLICY {}, using oldest", other);
+                PrunedBlockPolicy::Oldest
+            }
+        }
+    }
+}
+
+/// Whether `e` is the node saying it no longer has a block, e.g. one older than a pruning
+/// horizon. Providers each word this differently.
+pub(crate) fn is_pruned(e: &impl std::fmt::Display) -> bool {
+    let message = e.to_string().to_lowercase();
+
+    [
+        "pruned",
+        "missing trie node",
+        "header not found",
+        "too old",
+        "not available",
+        "unknown block",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// The block to resume from after the node refused `from` as pruned, per `PrunedBlockPolicy`.
+/// If the head can't be read, `from` is returned to try again later.
+pub(crate) async fn fall_back<M: Middleware + 'static>(
+    client: &Arc<M>,
+    flooring: &[Address],
+    from: u64,
+    e: impl Into<eyre::Report>,
+) -> eyre::Result<u64> {
+    let e = e.into();
+    error!("Node no longer has block {}: {}", from, e);
+
+    let policy = PrunedBlockPolicy::from_env();
+    if policy == PrunedBlockPolicy::Fail {
+        return Err(e);
+    }
+
+    let head = match client.get_block_number().await {
+        Ok(head) => head.as_u64(),
+        Err(e) => {
+            error!("Error getting block number: {:?}", e);
+            return Ok(from);
+        }
+    };
+
+    let block = match policy {
+        PrunedBlockPolicy::Oldest => oldest_available(client, flooring, from, head).await,
+        _ => head,
+    };
+    warn!(
+        "Resuming from block {}, events in blocks {} to {} are skipped",
+        block,
+        from,
+        block.saturating_sub(1)
+    );
+
+    Ok(block)
+}
+
+/// Bisect `(pruned, head]` for the oldest block the node still returns logs for.
+async fn oldest_available<M: Middleware + 'static>(
+    client: &Arc<M>,
+    flooring: &[Address],
+    pruned: u64,
+    head: u64,
+) -> u64 {
+    let (mut low, mut high) = (pruned, head);
+
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        let query = Contract::event_of_type::<FragmentNftFilter>(client.clone())
+            .address(ValueOrArray::Array(flooring.to_vec()))
+            .from_block(mid)
+            .to_block(mid)
+            .query()
+            .await;
+
+        match query {
+            Err(e) if is_pruned(&e) => low = mid,
+            _ => high = mid,
+        }
+    }
+
+    high
+}
+
+/// Fetch every event in `[from, to]`. Some providers silently truncate large responses, so a
+/// response with `log_cap` or more events is assumed to be incomplete and its range is split in
+/// half and re-queried until each part comes back under the cap.
+async fn query_complete<M: Middleware + 'static>(
+    client: &Arc<M>,
+    flooring: &[Address],
+    collection_topics: Option<&[H256]>,
+    from: u64,
+    to: u64,
+    log_cap: usize,
+) -> eyre::Result<Vec<(FragmentNftFilter, LogMeta)>> {
+    let mut ranges = vec![(from, to)];
+    let mut events = Vec::new();
+
+    while let Some((from, to)) = ranges.pop() {
+        let mut event = Contract::event_of_type::<FragmentNftFilter>(client.clone())
+            .address(ValueOrArray::Array(flooring.to_vec()))
+            .from_block(from)
+            .to_block(to);
+
+        if let Some(topics) = collection_topics {
+            event = event.topic3(ValueOrArray::Array(topics.to_vec()));
+        }
+
+        let logs = event.query_with_meta().await?;
+
+        if log_cap > 0 && logs.len() >= log_cap {
+            if from < to {
+                let mid = from + (to - from) / 2;
+                warn!(
+                    "Blocks {} to {} returned {} logs, likely capped, splitting at {}",
+                    from,
+                    to,
+                    logs.len(),
+                    mid
+                );
+                // lower half last so it is popped first and events stay in block order
+                ranges.push((mid + 1, to));
+                ranges.push((from, mid));
+                continue;
+            }
+
+            warn!(
+                "Block {} alone returned {} logs, some events may be missing",
+                from,
+                logs.len()
+            );
+        }
+
+        events.extend(logs);
+    }
+
+    Ok(events)
+}
+
+/// `interval` doubled for every consecutive failure after the first, up to `max`.
+fn backoff(interval: Duration, max: Duration, failures: u32) -> Duration {
+    let factor = 1u32 << failures.saturating_sub(1).min(16);
+    interval.saturating_mul(factor).min(max)
+}
+
+/// Sleep for `interval` plus up to `jitter_ms` so multiple monitors don't poll in lockstep.
+async fn sleep(interval: Duration, jitter_ms: u64) {
+    let jitter = match jitter_ms {
+        0 => Duration::ZERO,
+        max => Duration::from_millis(rand::thread_rng().gen_range(0..=max)),
+    };
+
+    tokio::time::sleep(interval + jitter).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::Token;
+    use ethers::contract::EthEvent;
+    use ethers::types::{Log, U256, U64};
+
+    use super::*;
+
+    /// A `FragmentNft` log in `block`, fragmenting one token whose id is the block number.
+    fn fragment_log(block: u64) -> Log {
+        Log {
+            topics: vec![
+                FragmentNftFilter::signature(),
+                H256::zero(),
+                H256::zero(),
+                H256::zero(),
+            ],
+            data: ethers::abi::encode(&[Token::Array(vec![Token::Uint(U256::from(block))])]).into(),
+            block_number: Some(block.into()),
+            block_hash: Some(H256::zero()),
+            transaction_hash: Some(H256::zero()),
+            transaction_index: Some(U64::zero()),
+            log_index: Some(U256::zero()),
+            ..Log::default()
+        }
+    }
+
+    fn blocks(events: &[(FragmentNftFilter, LogMeta)]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|(_, meta)| meta.block_number.as_u64())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn splits_a_capped_range_until_complete() {
+        let (provider, mock) = Provider::mocked();
+        // the mock answers last pushed first
+        mock.push::<Vec<Log>, _>(vec![fragment_log(3), fragment_log(4)])
+            .unwrap();
+        mock.push::<Vec<Log>, _>(vec![fragment_log(1), fragment_log(2)])
+            .unwrap();
+        // blocks 1 to 4 have four events, but the provider stops at its cap of three
+        mock.push::<Vec<Log>, _>(vec![fragment_log(1), fragment_log(2), fragment_log(3)])
+            .unwrap();
+
+        let events = query_complete(&Arc::new(provider), &[Address::zero()], None, 1, 4, 3)
+            .await
+            .unwrap();
+
+        assert_eq!(blocks(&events), vec![1, 2, 3, 4]);
+        assert_eq!(events[3].0.token_ids, vec![U256::from(4)]);
+    }
+
+    #[tokio::test]
+    async fn keeps_a_capped_single_block() {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Vec<Log>, _>(vec![fragment_log(5), fragment_log(5)])
+            .unwrap();
+
+        let events = query_complete(&Arc::new(provider), &[Address::zero()], None, 5, 5, 2)
+            .await
+            .unwrap();
+
+        assert_eq!(blocks(&events), vec![5, 5]);
+    }
+
+    #[tokio::test]
+    async fn no_cap_never_splits() {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Vec<Log>, _>(vec![fragment_log(1), fragment_log(2)])
+            .unwrap();
+
+        let events = query_complete(&Arc::new(provider), &[Address::zero()], None, 1, 10, 0)
+            .await
+            .unwrap();
+
+        assert_eq!(blocks(&events), vec![1, 2]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use tracing::{error, info, warn};
+
+use crate::clock::Clock;
+use crate::message::Message;
+use crate::state::AppState;
+use crate::{config, notifier};
+
+/// Recent MuToken derived prices per collection, to spot sharp moves.
+///
+/// Samples older than `window` are dropped whenever a collection is sampled, and a collection's
+/// history restarts from the current price after every alert, so one move is reported once.
+pub(crate) struct PriceMoves {
+    clock: Arc<dyn Clock>,
+    window: Duration,
+    /// smallest move worth an alert, in percent either way
+    threshold_pct: f64,
+    samples: Mutex<HashMap<String, VecDeque<(Instant, Decimal)>>>,
+}
+
+impl PriceMoves {
+    pub(crate) fn new(clock: Arc<dyn Clock>, window: Duration, threshold_pct: f64) -> Self {
+        PriceMoves {
+            clock,
+            window,
+            threshold_pct,
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a price, returning `(earlier price, percent change)` if it moved more than the
+    /// threshold since the oldest sample still inside the window.
+    fn sample(&self, collection: &str, price: Decimal) -> Option<(Decimal, f64)> {
+        let now = self.clock.now();
+        let mut samples = self.samples.lock().unwrap();
+        let history = samples.entry(collection.to_string()).or_default();
+
+        while history
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+        {
+            history.pop_front();
+        }
+
+        let earliest = history.front().map(|(_, earliest)| *earliest);
+        history.push_back((now, price));
+
+        let earliest = earliest.filter(|earliest| !earliest.is_zero())?;
+        let change_pct = ((price - earliest) / earliest * Decimal::ONE_HUNDRED).to_f64()?;
+        if change_pct.abs() < self.threshold_pct {
+            return None;
+        }
+
+        history.clear();
+        history.push_back((now, price));
+        Some((earliest, change_pct))
+    }
+}
+
+/// Poll the MuToken price of every `MONITORED_COLLECTIONS` entry and alert on sharp moves. These
+/// alerts are separate from fragment events and are tagged as price moves.
+pub(crate) async fn watch(state: Arc<AppState>, collections: Vec<String>) {
+    let Some(price_moves) = &state.price_moves else {
+        return;
+    };
+
+    if collections.is_empty() {
+        warn!("PRICE_MOVE_PCT is set but MONITORED_COLLECTIONS is empty, not watching prices");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config::var_or(
+        "PRICE_MOVE_INTERVAL_SECS",
+        300,
+    )));
+
+    loop {
+        interval.tick().await;
+
+        for collection in &collections {
+            let mu_token = match Message::default()
+                .get_mu_token_details(&state, collection)
+                .await
+            {
+                Ok(mu_token) => mu_token,
+                Err(e) => {
+                    error!("Error getting MuToken price for {}: {:?}", collection, e);
+                    continue;
+                }
+            };
+
+            let price = mu_token.derived_price();
+            let Some((earlier, change_pct)) = price_moves.sample(collection, price) else {
+                continue;
+            };
+
+            let text = format!(
+                "{} MuToken price move for {}: {:+.1}% in under {}\n{} ETH -> <a href=\"{}\">{} ETH</a>",
+                if change_pct > 0.0 { "📈" } else { "📉" },
+                collection,
+                change_pct,
+                crate::message::format_age(
+                    chrono::Duration::from_std(price_moves.window)
+                        .unwrap_or_else(|_| chrono::Duration::zero())
+                ),
+                earlier,
+                mu_token.dexscreener_link(),
+                price,
+            );
+            info!("{}", text);
+            notifier::notify_all_text(&state.notifiers, &text).await;
+        }
+    }
+}
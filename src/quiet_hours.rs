@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+use tracing::warn;
+
+use crate::config;
+use crate::message::Message;
+
+/// What to send for the alerts held back once quiet hours end, from `QUIET_HOURS_FLUSH`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QuietHoursFlush {
+    /// one line per held alert
+    Summary,
+    /// every held alert in full
+    All,
+}
+
+/// A daily window, in a named time zone, during which only urgent alerts are sent. The rest are
+/// held and sent once the window ends. The window may wrap past midnight, e.g. 23:00 to 07:00.
+pub(crate) struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+    tz: Tz,
+    /// alerts at or above this profit are sent even during quiet hours
+    urgent_profit: Option<Decimal>,
+    pub(crate) flush: QuietHoursFlush,
+    held: Mutex<Vec<Message>>,
+}
+
+impl QuietHours {
+    /// Only enabled when both `QUIET_HOURS_START` and `QUIET_HOURS_END` are set, as `HH:MM`.
+    pub(crate) fn from_env() -> Option<Self> {
+        let parse_time = |key: &str| {
+            let value = dotenv::var(key).ok()?;
+            NaiveTime::parse_from_str(&value, "%H:%M")
+                .map_err(|e| warn!("Could not parse {key}={value}: {e}"))
+                .ok()
+        };
+
+        let tz = dotenv::var("QUIET_HOURS_TZ")
+            .ok()
+            .and_then(|tz| {
+                tz.parse::<Tz>()
+                    .map_err(|e| warn!("Could not parse QUIET_HOURS_TZ={tz}: {e}"))
+                    .ok()
+            })
+            .unwrap_or(Tz::UTC);
+
+        Some(QuietHours {
+            start: parse_time("QUIET_HOURS_START")?,
+            end: parse_time("QUIET_HOURS_END")?,
+            tz,
+            urgent_profit: config::var_opt("QUIET_HOURS_URGENT_PROFIT"),
+            flush: match dotenv::var("QUIET_HOURS_FLUSH").as_deref() {
+                Ok("all") => QuietHoursFlush::All,
+                _ => QuietHoursFlush::Summary,
+            },
+            held: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        let time = now.with_timezone(&self.tz).time();
+
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Hold `msg` if it is quiet hours and the alert isn't urgent, otherwise hand it back.
+    pub(crate) fn hold(&self, msg: Message) -> Option<Message> {
+        let urgent = self
+            .urgent_profit
+            .is_some_and(|urgent_profit| msg.total_profit >= urgent_profit);
+        if !self.is_quiet(Utc::now()) || urgent {
+            return Some(msg);
+        }
+
+        self.held.lock().unwrap().push(msg);
+        None
+    }
+
+    /// The held alerts, once quiet hours are over.
+    pub(crate) fn take_if_over(&self) -> Option<Vec<Message>> {
+        if self.is_quiet(Utc::now()) {
+            return None;
+        }
+
+        let mut held = self.held.lock().unwrap();
+        if held.is_empty() {
+            return None;
+        }
+
+        Some(std::mem::take(&mut *held))
+    }
+
+    /// Everything still held, for flushing on exit.
+    pub(crate) fn take_all(&self) -> Vec<Message> {
+        std::mem::take(&mut *self.held.lock().unwrap())
+    }
+}
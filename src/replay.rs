@@ -0,0 +1,124 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use ethers::prelude::LogMeta;
+use ethers::types::{Address, H256, U256, U64};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tracing::{error, info, warn};
+
+use crate::FragmentNftFilter;
+
+/// One recorded `FragmentNft` event and its log metadata, stored one JSON object per line.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Record {
+    pub(crate) operator: Address,
+    pub(crate) on_behalf_of: Address,
+    pub(crate) collection: Address,
+    pub(crate) token_ids: Vec<U256>,
+    pub(crate) address: Address,
+    pub(crate) block_number: U64,
+    pub(crate) block_hash: H256,
+    pub(crate) transaction_hash: H256,
+    pub(crate) transaction_index: U64,
+    pub(crate) log_index: U256,
+}
+
+impl Record {
+    pub(crate) fn new(log: &FragmentNftFilter, meta: &LogMeta) -> Self {
+        Record {
+            operator: log.operator,
+            on_behalf_of: log.on_behalf_of,
+            collection: log.collection,
+            token_ids: log.token_ids.clone(),
+            address: meta.address,
+            block_number: meta.block_number,
+            block_hash: meta.block_hash,
+            transaction_hash: meta.transaction_hash,
+            transaction_index: meta.transaction_index,
+            log_index: meta.log_index,
+        }
+    }
+
+    pub(crate) fn into_event(self) -> (FragmentNftFilter, LogMeta) {
+        let log = FragmentNftFilter {
+            operator: self.operator,
+            on_behalf_of: self.on_behalf_of,
+            collection: self.collection,
+            token_ids: self.token_ids,
+        };
+
+        let meta = LogMeta {
+            address: self.address,
+            block_number: self.block_number,
+            block_hash: self.block_hash,
+            transaction_hash: self.transaction_hash,
+            transaction_index: self.transaction_index,
+            log_index: self.log_index,
+        };
+
+        (log, meta)
+    }
+}
+
+/// Appends every live event to a file that `run` can later replay.
+pub(crate) struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Recorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub(crate) fn record(&self, log: &FragmentNftFilter, meta: &LogMeta) {
+        let line = match serde_json::to_string(&Record::new(log, meta)) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Error serializing event for recording: {:?}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{}", line) {
+            error!("Error recording event: {:?}", e);
+        }
+    }
+}
+
+/// Replay recorded events from `path` through the pipeline as if they came from the stream.
+pub(crate) async fn run(
+    path: String,
+    events: Sender<(FragmentNftFilter, LogMeta)>,
+) -> eyre::Result<()> {
+    let reader = BufReader::new(File::open(&path)?);
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = match serde_json::from_str::<Record>(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Skipping line {} of {}: {:?}", number + 1, path, e);
+                continue;
+            }
+        };
+
+        if events.send(record.into_event()).await.is_err() {
+            break;
+        }
+    }
+
+    info!("Finished replaying {}", path);
+
+    Ok(())
+}
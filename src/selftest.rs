@@ -0,0 +1,97 @@
+use std::future::Future;
+
+use ethers::providers::{Http, Middleware, Provider, Ws};
+use teloxide::prelude::*;
+
+use crate::blur::BlurConfig;
+use crate::error::{get_json, ApiProvider};
+
+/// A collection every API knows about, for the calls that need one.
+const PROBE_COLLECTION: &str = "0xbc4ca0eda7647a8ab7c2061c2e118a18a936f13d";
+
+/// Make one minimal call to every configured service and report each as OK or FAIL.
+/// Returns true only if every check passed.
+pub(crate) async fn run(http: &Provider<Http>) -> bool {
+    let client = crate::http::client();
+    let mut ok = true;
+
+    ok &= check("rpc (http)", async { http.get_block_number().await }).await;
+
+    if let Ok(wss) = dotenv::var("WSS_RPC") {
+        ok &= check("rpc (wss)", async {
+            let provider = Provider::<Ws>::connect(wss).await?;
+            eyre::Ok(provider.get_block_number().await?)
+        })
+        .await;
+    }
+
+    ok &= check("paraswap", async {
+        // price one USDC in ETH
+        let url = "https://api.paraswap.io/prices/?srcToken=0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE&destToken=0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48&amount=1000000&srcDecimals=18&destDecimals=6&side=BUY&network=1";
+        get_json(ApiProvider::ParaSwap, client.get(url)).await
+    })
+    .await;
+
+    ok &= check("reservoir", async {
+        let key = dotenv::var("RESERVOIR_API_KEY")?;
+        let url = format!(
+            "https://api.reservoir.tools/collections/v7?id={}",
+            PROBE_COLLECTION
+        );
+        eyre::Ok(
+            get_json(
+                ApiProvider::Reservoir,
+                client.get(url).header("x-api-key", key),
+            )
+            .await?,
+        )
+    })
+    .await;
+
+    ok &= check("deepnftvalue", async {
+        let key = dotenv::var("DEEP_API_KEY")?;
+        let url = "https://api.deepnftvalue.com/v1/collections?limit=1";
+        let req = client.get(url).header(reqwest::header::AUTHORIZATION, key);
+        eyre::Ok(get_json(ApiProvider::DeepNftValue, req).await?)
+    })
+    .await;
+
+    if let Ok(key) = dotenv::var("ETHERSCAN_API_KEY") {
+        ok &= check("etherscan", async {
+            let url = format!(
+                "https://api.etherscan.io/api?module=proxy&action=eth_blockNumber&apikey={}",
+                key
+            );
+            get_json(ApiProvider::Etherscan, client.get(url)).await
+        })
+        .await;
+    }
+
+    if let Some(blur) = BlurConfig::from_env() {
+        ok &= check("blur", blur.top_bid(PROBE_COLLECTION)).await;
+    }
+
+    ok &= check("telegram", async {
+        let bot = Bot::new(dotenv::var("TELEGRAM_BOT_TOKEN")?);
+        eyre::Ok(bot.get_me().await?)
+    })
+    .await;
+
+    ok
+}
+
+async fn check<T, E>(name: &str, call: impl Future<Output = Result<T, E>>) -> bool
+where
+    E: std::fmt::Display,
+{
+    match call.await {
+        Ok(_) => {
+            println!("OK   {}", name);
+            true
+        }
+        Err(e) => {
+            println!("FAIL {}: {}", name, e);
+            false
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ethers::types::U256;
+use rust_decimal::Decimal;
+use teloxide::types::MessageId;
+
+use crate::clock::Clock;
+use crate::message::Message;
+
+/// Telegram messages already sent per `(collection, token_id)`, so a newer alert for the same
+/// token with a better top bid can update the existing message instead of posting another one.
+pub(crate) struct SentAlerts {
+    clock: Arc<dyn Clock>,
+    /// how long after sending a message it may still be edited
+    window: Duration,
+    sent: Mutex<HashMap<(String, U256), Sent>>,
+}
+
+struct Sent {
+    message_id: MessageId,
+    /// the alert's total profit
+    profit: Decimal,
+    sent_at: Instant,
+}
+
+impl SentAlerts {
+    pub(crate) fn new(clock: Arc<dyn Clock>, window: Duration) -> Self {
+        SentAlerts {
+            clock,
+            window,
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The most recent message still inside the edit window for any of `msg`'s tokens, if `msg`
+    /// is more profitable than it, i.e. one of its tokens got a better bid.
+    pub(crate) fn improved_on(&self, msg: &Message) -> Option<MessageId> {
+        let now = self.clock.now();
+        let mut sent = self.sent.lock().unwrap();
+
+        // forget anything that can no longer be edited
+        sent.retain(|_, sent| now.duration_since(sent.sent_at) < self.window);
+
+        msg.token_keys()
+            .iter()
+            .filter_map(|key| sent.get(key))
+            .max_by_key(|sent| sent.sent_at)
+            .filter(|sent| msg.total_profit > sent.profit)
+            .map(|sent| sent.message_id)
+    }
+
+    pub(crate) fn record(&self, msg: &Message, message_id: MessageId) {
+        let now = self.clock.now();
+        let mut sent = self.sent.lock().unwrap();
+
+        for key in msg.token_keys() {
+            sent.insert(
+                key,
+                Sent {
+                    message_id,
+                    profit: msg.total_profit,
+                    sent_at: now,
+                },
+            );
+        }
+    }
+}
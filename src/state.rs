@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ethers::types::{Address, U256};
+use tokio::sync::broadcast;
+
+use crate::batch::Batch;
+use crate::chain::ChainConfig;
+use crate::checkpoint::Checkpoint;
+use crate::clock::{Clock, SystemClock};
+use crate::config;
+use crate::cooldown::Cooldown;
+use crate::cycles::Cycles;
+use crate::dedup::{self, Dedup};
+use crate::heartbeat::Heartbeat;
+use crate::ledger::Ledger;
+use crate::message::CollectionInfo;
+use crate::metadata::TokenMetadata;
+use crate::metrics::Metrics;
+use crate::mute::MuteList;
+use crate::notifier::{self, Notifier};
+use crate::price_moves::PriceMoves;
+use crate::quiet_hours::QuietHours;
+use crate::sent_alerts::SentAlerts;
+use crate::ttl_cache::TtlCache;
+use crate::Flooring;
+
+/// Everything the monitor shares between the event loop, the command handlers and the metrics
+/// server. Built once per monitored chain in `main` and passed around as `Arc<AppState>`. The
+/// notifiers, ledger, metrics, mute list, dedup store and alert stream are shared by every
+/// chain, the rest is per chain.
+///
+/// Locking discipline: each piece of mutable state owns its own `std::sync::Mutex` (or atomics).
+/// A lock is only held for a single lookup or update, never across an `.await` and never while
+/// taking another lock, so the sync mutexes are safe in async code and can't deadlock.
+pub(crate) struct AppState {
+    /// the chain this state monitors
+    pub(crate) chain: ChainConfig,
+    /// true when more than one chain is monitored, so alerts need to say which one they are for.
+    /// Set in `main` once every chain is known.
+    pub(crate) multi_chain: bool,
+    /// the contract answering `collectionInfo`, with its shared HTTP provider
+    pub(crate) flooring: Flooring,
+    /// the last event handled, to resume from after a restart
+    pub(crate) checkpoint: Checkpoint,
+    /// per-collection alert cooldown
+    pub(crate) cooldown: Cooldown,
+    /// repeat events and recently alerted tokens
+    pub(crate) dedup: Arc<Dedup>,
+    /// recently sent Telegram messages that may still be edited
+    pub(crate) sent_alerts: Arc<SentAlerts>,
+    /// every output channel alerts are delivered to
+    pub(crate) notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    /// paper trading profit of every alert sent
+    pub(crate) ledger: Arc<Ledger>,
+    pub(crate) metrics: Arc<Metrics>,
+    /// collections muted at runtime with /mute
+    pub(crate) mute_list: Arc<MuteList>,
+    /// every alert as JSON, for the `/events` stream on the metrics server
+    pub(crate) opportunities: broadcast::Sender<String>,
+    /// `collectionInfo` results by collection address
+    pub(crate) collection_info: TtlCache<String, CollectionInfo>,
+    /// on-chain token metadata by `(collection, token_id)`, when `RESOLVE_TOKEN_URI` is set
+    pub(crate) token_metadata: TtlCache<(String, U256), TokenMetadata>,
+    /// slugs resolved at startup for `MONITORED_COLLECTIONS`, by collection address
+    pub(crate) collection_slugs: Mutex<HashMap<String, String>>,
+    /// fragments per NFT by collection address, expiring along with `collection_info`
+    pub(crate) fragments_per_nft: TtlCache<String, U256>,
+    /// MuToken symbols by token address, read on chain once per token to check its identity
+    pub(crate) mu_token_symbols: Mutex<HashMap<Address, String>>,
+    /// alerts waiting to be sent together, when `BATCH_WINDOW_MS` is set
+    pub(crate) batch: Option<Batch>,
+    /// alerts held back overnight, when `QUIET_HOURS_START` and `QUIET_HOURS_END` are set
+    pub(crate) quiet_hours: Option<QuietHours>,
+    /// fragmented tokens awaiting redemption, when `TRACK_CYCLES` is set
+    pub(crate) cycles: Option<Cycles>,
+    /// recent MuToken prices of `MONITORED_COLLECTIONS`, when `PRICE_MOVE_PCT` is set
+    pub(crate) price_moves: Option<PriceMoves>,
+    /// when the last event was processed, when `HEARTBEAT_HOURS` is set
+    pub(crate) heartbeat: Option<Heartbeat>,
+}
+
+/// The parts of `AppState` every chain shares.
+struct Shared {
+    sent_alerts: Arc<SentAlerts>,
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    ledger: Arc<Ledger>,
+    metrics: Arc<Metrics>,
+    mute_list: Arc<MuteList>,
+    dedup: Arc<Dedup>,
+    opportunities: broadcast::Sender<String>,
+}
+
+impl Shared {
+    fn from_env(clock: &Arc<dyn Clock>) -> Self {
+        // alerts for a token seen again within this window edit the original message
+        let sent_alerts = Arc::new(SentAlerts::new(
+            clock.clone(),
+            Duration::from_secs(config::var_or("EDIT_WINDOW_SECS", 0)),
+        ));
+
+        Shared {
+            dedup: Arc::new(Dedup::new(
+                dedup::store_from_env(clock.clone()),
+                Duration::from_secs(config::var_or("DEDUP_TX_WINDOW_SECS", 3_600)),
+                Duration::from_secs(config::var_or("DEDUP_TOKEN_WINDOW_SECS", 0)),
+            )),
+            notifiers: Arc::new(notifier::from_env(sent_alerts.clone())),
+            sent_alerts,
+            ledger: Arc::new(Ledger::new(
+                clock.clone(),
+                dotenv::var("LEDGER_FILE").ok().map(Into::into),
+                match config::var_or("LEDGER_RESET_HOURS", 0u64) {
+                    0 => None,
+                    hours => Some(Duration::from_secs(hours * 3600)),
+                },
+            )),
+            metrics: Arc::new(Metrics::new(Metrics::profit_buckets())),
+            mute_list: Arc::new(MuteList::load(
+                dotenv::var("MUTE_FILE")
+                    .unwrap_or_else(|_| "muted_collections.json".to_string())
+                    .into(),
+            )),
+            // subscribers falling further behind than this skip the missed alerts
+            opportunities: broadcast::channel(config::var_or("SSE_BUFFER", 64)).0,
+        }
+    }
+}
+
+impl AppState {
+    pub(crate) fn from_env(chain: ChainConfig, flooring: Flooring) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let shared = Shared::from_env(&clock);
+
+        AppState::new(chain, flooring, clock, shared)
+    }
+
+    /// State for another chain, sharing this state's notifiers, ledger, metrics, mute list, dedup
+    /// store and alert stream. Only the per chain parts are built.
+    pub(crate) fn for_chain(&self, chain: ChainConfig, flooring: Flooring) -> Self {
+        let shared = Shared {
+            sent_alerts: self.sent_alerts.clone(),
+            notifiers: self.notifiers.clone(),
+            ledger: self.ledger.clone(),
+            metrics: self.metrics.clone(),
+            mute_list: self.mute_list.clone(),
+            dedup: self.dedup.clone(),
+            opportunities: self.opportunities.clone(),
+        };
+
+        AppState {
+            multi_chain: self.multi_chain,
+            ..AppState::new(chain, flooring, Arc::new(SystemClock), shared)
+        }
+    }
+
+    fn new(chain: ChainConfig, flooring: Flooring, clock: Arc<dyn Clock>, shared: Shared) -> Self {
+        let collection_info_ttl =
+            Duration::from_secs(config::var_or("COLLECTION_INFO_TTL_SECS", 300));
+
+        AppState {
+            checkpoint: Checkpoint::load(
+                chain.checkpoint_file.clone(),
+                chain.starting_block.is_none(),
+            ),
+            chain,
+            multi_chain: false,
+            flooring,
+            cooldown: Cooldown::new(
+                clock.clone(),
+                Duration::from_secs(config::var_or("ALERT_COOLDOWN_SECS", 0)),
+            ),
+            dedup: shared.dedup,
+            sent_alerts: shared.sent_alerts,
+            notifiers: shared.notifiers,
+            ledger: shared.ledger,
+            metrics: shared.metrics,
+            mute_list: shared.mute_list,
+            opportunities: shared.opportunities,
+            collection_info: TtlCache::new(clock.clone(), collection_info_ttl),
+            token_metadata: TtlCache::new(
+                clock.clone(),
+                Duration::from_secs(config::var_or("TOKEN_METADATA_TTL_SECS", 86_400)),
+            ),
+            collection_slugs: Mutex::new(HashMap::new()),
+            fragments_per_nft: TtlCache::new(clock.clone(), collection_info_ttl),
+            mu_token_symbols: Mutex::new(HashMap::new()),
+            quiet_hours: QuietHours::from_env(),
+            cycles: config::var_or("TRACK_CYCLES", false).then(|| {
+                Cycles::new(
+                    clock.clone(),
+                    Duration::from_secs(config::var_or("CYCLE_WINDOW_SECS", 86_400)),
+                )
+            }),
+            price_moves: config::var_opt("PRICE_MOVE_PCT").map(|threshold_pct| {
+                PriceMoves::new(
+                    clock.clone(),
+                    Duration::from_secs(config::var_or("PRICE_MOVE_WINDOW_SECS", 3_600)),
+                    threshold_pct,
+                )
+            }),
+            heartbeat: match config::var_or("HEARTBEAT_HOURS", 0u64) {
+                0 => None,
+                hours => Some(Heartbeat::new(
+                    clock.clone(),
+                    Duration::from_secs(hours * 3600),
+                    config::list("HEARTBEAT_NOTIFIERS"),
+                )),
+            },
+            batch: match config::var_or("BATCH_WINDOW_MS", 0) {
+                0 => None,
+                window_ms => Some(Batch::new(
+                    clock,
+                    Duration::from_millis(window_ms),
+                    config::var_or("BATCH_MAX_SIZE", 10),
+                    config::var_opt("BATCH_BYPASS_PROFIT"),
+                )),
+            },
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// A map whose entries expire `ttl` after they were inserted.
+pub(crate) struct TtlCache<K, V> {
+    clock: Arc<dyn Clock>,
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (V, Instant)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    pub(crate) fn new(clock: Arc<dyn Clock>, ttl: Duration) -> Self {
+        TtlCache {
+            clock,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cached value for `key`, unless it has expired (in which case it is evicted).
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(key) {
+            Some((value, inserted)) if now.duration_since(*inserted) < self.ttl => {
+                Some(value.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, key: K, value: V) {
+        let now = self.clock.now();
+        self.entries.lock().unwrap().insert(key, (value, now));
+    }
+}
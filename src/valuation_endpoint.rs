@@ -0,0 +1,110 @@
+use chrono::{DateTime, Utc};
+use ethers::types::U256;
+use rust_decimal::Decimal;
+use tracing::{info, warn};
+
+use crate::error::{get_json, ApiProvider, MessageError};
+use crate::message::{parse_decimal, parse_timestamp};
+
+/// How the endpoint's valuation is combined with DeepNFTValue's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ValuationEndpointMode {
+    /// only ever ask the endpoint
+    Override,
+    /// ask DeepNFTValue first, and the endpoint when that has no valuation or fails
+    Fallback,
+}
+
+/// A user-run pricing service, configured by `VALUATION_ENDPOINT`.
+///
+/// Request: `GET {VALUATION_ENDPOINT}?collection={collection}&token_id={token_id}`, with the
+/// collection as a lowercase `0x` address and the token id in decimal. `VALUATION_ENDPOINT_AUTH`
+/// is sent as the `Authorization` header when set.
+///
+/// Response: a 2xx with a JSON object
+///
+/// ```text
+/// {"price": "1.25", "updated_at": "2024-01-01T00:00:00Z", "url": "https://example.com/1"}
+/// ```
+///
+/// - `price`: the valuation in ETH, as a string or a number. `null` or missing means the
+///   endpoint has no valuation for the token.
+/// - `updated_at`, optional: RFC 3339, `YYYY-MM-DD` or unix seconds. Checked against
+///   `VALUATION_MAX_AGE_HOURS` like DeepNFTValue's.
+/// - `url`, optional: linked from the alert.
+///
+/// Anything else, including a non-2xx status or an unparseable price, is an error.
+#[derive(Clone, Debug)]
+pub(crate) struct ValuationEndpoint {
+    url: String,
+    auth: Option<String>,
+    /// shown on alerts in place of "DeepNFTValue", from `VALUATION_ENDPOINT_NAME`
+    pub(crate) name: String,
+    pub(crate) mode: ValuationEndpointMode,
+}
+
+/// One valuation as returned by the endpoint.
+#[derive(Clone, Debug)]
+pub(crate) struct EndpointValuation {
+    pub(crate) price: Decimal,
+    pub(crate) updated_at: Option<DateTime<Utc>>,
+    pub(crate) url: Option<String>,
+}
+
+impl ValuationEndpoint {
+    pub(crate) fn from_env() -> Option<Self> {
+        let url = dotenv::var("VALUATION_ENDPOINT").ok()?;
+
+        let mode = match dotenv::var("VALUATION_ENDPOINT_MODE").as_deref() {
+            Ok("fallback") => ValuationEndpointMode::Fallback,
+            Ok("override") | Err(_) => ValuationEndpointMode::Override,
+            Ok(other) => {
+                warn!("Unknown VALUATION_ENDPOINT_MODE {}, using override", other);
+                ValuationEndpointMode::Override
+            }
+        };
+
+        Some(ValuationEndpoint {
+            url,
+            auth: dotenv::var("VALUATION_ENDPOINT_AUTH").ok(),
+            name: dotenv::var("VALUATION_ENDPOINT_NAME").unwrap_or_else(|_| "Custom".to_string()),
+            mode,
+        })
+    }
+
+    pub(crate) async fn valuation(
+        &self,
+        collection: &str,
+        token_id: U256,
+    ) -> Result<Option<EndpointValuation>, MessageError> {
+        let client = crate::http::client();
+
+        let mut req = client
+            .get(&self.url)
+            .query(&[
+                ("collection", collection.to_lowercase()),
+                ("token_id", token_id.to_string()),
+            ])
+            .header("accept", "application/json");
+
+        if let Some(auth) = &self.auth {
+            req = req.header(reqwest::header::AUTHORIZATION, auth);
+        }
+
+        let json = get_json(ApiProvider::ValuationEndpoint, req).await?;
+
+        if json["price"].is_null() {
+            info!("No {} valuation for token {}", self.name, token_id);
+            return Ok(None);
+        }
+
+        let price = parse_decimal(&json["price"])
+            .map_err(|e| MessageError::parse(ApiProvider::ValuationEndpoint, e))?;
+
+        Ok(Some(EndpointValuation {
+            price,
+            updated_at: parse_timestamp(&json["updated_at"]),
+            url: json["url"].as_str().map(str::to_string),
+        }))
+    }
+}